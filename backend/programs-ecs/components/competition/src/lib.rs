@@ -2,6 +2,9 @@ use bolt_lang::*;
 
 declare_id!("FPKpeKHnfYuYo8JDiDW7mNzZB8qgf1mLYwpQAcbGyVhJ");
 
+pub const MAX_NAME_LEN: usize = 50;
+pub const MAX_ALLOWED_PAIRS: usize = 16;
+
 #[component]
 #[derive(Default)]
 pub struct Competition {
@@ -11,6 +14,27 @@ pub struct Competition {
     pub total_participants: u64,
     pub prize_pool: u64,
     pub is_active: bool,
-    #[max_len(50)]
+    #[max_len(MAX_NAME_LEN)]
     pub name: String,
-}
\ No newline at end of file
+    pub entry_fee: u64,
+    pub starting_balance: u64,
+    #[max_len(MAX_ALLOWED_PAIRS)]
+    pub allowed_pairs: Vec<u8>, // empty = every pair
+    pub allow_late_join: bool,  // join after start_time
+}
+
+impl Competition {
+    pub fn has_started(&self, now: i64) -> bool {
+        now >= self.start_time
+    }
+
+    /// No trading once end_time passes, even before is_active is flipped: the positions still
+    /// open are only closed by the settlement, at their value at end_time
+    pub fn has_ended(&self, now: i64) -> bool {
+        now >= self.end_time
+    }
+
+    pub fn is_pair_allowed(&self, pair_index: u8) -> bool {
+        self.allowed_pairs.is_empty() || self.allowed_pairs.contains(&pair_index)
+    }
+}
@@ -16,6 +16,8 @@ pub struct Position {
     pub is_open: bool,
     pub opened_at: i64,
     pub closed_at: i64,
+    pub competition: Pubkey,     // competition component the position was opened in
+    pub trading_account: Pubkey, // trading account component holding its margin
 }
 
 impl Default for Position {
@@ -33,6 +35,8 @@ impl Default for Position {
             is_open: false,
             opened_at: 0,
             closed_at: 0,
+            competition: Pubkey::default(),
+            trading_account: Pubkey::default(),
         }
     }
 }
\ No newline at end of file
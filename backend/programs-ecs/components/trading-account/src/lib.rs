@@ -12,4 +12,73 @@ pub struct TradingAccount {
     pub winning_trades: u64,
     pub losing_trades: u64,
     pub created_at: i64,
+    pub competition: Pubkey, // competition component the account joined
+    pub joined: bool,
+    pub locked_margin: u64, // margin of the open positions, not part of balance
+}
+
+impl TradingAccount {
+    pub fn is_bound_to(&self, owner: &Pubkey, competition: &Pubkey) -> bool {
+        self.joined && self.owner == *owner && self.competition == *competition
+    }
+
+    /// Moves the margin of a new position out of the balance, None if it isn't covered
+    pub fn lock_margin(&mut self, margin: u64) -> Option<()> {
+        let balance = self.balance.checked_sub(margin)?;
+        self.locked_margin = self.locked_margin.checked_add(margin)?;
+        self.balance = balance;
+        Some(())
+    }
+
+    /// Credits back the margin plus the PnL, a loss never exceeds the margin.
+    /// Returns the realized PnL.
+    pub fn release_margin(&mut self, margin: u64, pnl: i64) -> Option<i64> {
+        let realized = pnl.max(-i64::try_from(margin).ok()?);
+        let payout = u64::try_from(i64::try_from(margin).ok()?.checked_add(realized)?).ok()?;
+        self.locked_margin = self.locked_margin.checked_sub(margin)?;
+        self.balance = self.balance.checked_add(payout)?;
+        Some(realized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(balance: u64) -> TradingAccount {
+        TradingAccount {
+            balance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn margin_above_balance_is_rejected() {
+        let mut trading_account = account(100);
+        assert!(trading_account.lock_margin(101).is_none());
+        assert_eq!(trading_account.balance, 100);
+        assert_eq!(trading_account.locked_margin, 0);
+    }
+
+    #[test]
+    fn margin_round_trip_credits_pnl() {
+        let mut trading_account = account(100);
+        trading_account.lock_margin(40).unwrap();
+        assert_eq!(trading_account.balance, 60);
+        assert_eq!(trading_account.locked_margin, 40);
+
+        assert_eq!(trading_account.release_margin(40, 15), Some(15));
+        assert_eq!(trading_account.balance, 115);
+        assert_eq!(trading_account.locked_margin, 0);
+    }
+
+    #[test]
+    fn loss_is_capped_at_margin() {
+        let mut trading_account = account(100);
+        trading_account.lock_margin(40).unwrap();
+
+        assert_eq!(trading_account.release_margin(40, -70), Some(-40));
+        assert_eq!(trading_account.balance, 60);
+        assert_eq!(trading_account.locked_margin, 0);
+    }
 }
\ No newline at end of file
@@ -1,4 +1,5 @@
 use bolt_lang::*;
+use paper_trading::get_pair_price;
 use position::Position;
 use trading_account::TradingAccount;
 use competition::Competition;
@@ -10,11 +11,49 @@ pub mod close_position {
 
     pub fn execute(ctx: Context<Components>, _args: Vec<u8>) -> Result<Components> {
         let clock = Clock::get()?;
+        let trading_account_key = ctx.accounts.trading_account.key();
 
         let position = &mut ctx.accounts.position;
         let trading_account = &mut ctx.accounts.trading_account;
+        let competition = &ctx.accounts.competition;
 
+        // Once end_time passes, the positions still open are settled: anyone can close them
+        // at their value at end_time, so that their margin and PnL count in the final balance
+        let settling = competition.has_ended(clock.unix_timestamp);
+        if !settling {
+            require!(competition.is_active, ErrorCode::CompetitionNotActive);
+            require!(competition.has_started(clock.unix_timestamp), ErrorCode::CompetitionNotStarted);
+        }
         require!(position.is_open, ErrorCode::PositionNotOpen);
+        require!(
+            settling || position.owner == *ctx.accounts.authority.key,
+            ErrorCode::NotPositionOwner
+        );
+        require!(
+            trading_account.is_bound_to(&position.owner, &competition.key()),
+            ErrorCode::NotJoined
+        );
+        require!(
+            position.competition == competition.key() && position.trading_account == trading_account_key,
+            ErrorCode::PositionAccountMismatch
+        );
+
+        // Remaining accounts: the Pyth price update, the pair config and the config of the
+        // paper trading program (feed id and oracle limits)
+        let [price_update, pair_config, config, ..] = ctx.remaining_accounts else {
+            return err!(ErrorCode::MissingOracleAccounts);
+        };
+        // A price published before the opening can't close the position, nor one published
+        // after end_time a settlement
+        let closed_at = if settling { competition.end_time } else { clock.unix_timestamp };
+        position.current_price = get_pair_price(
+            price_update,
+            pair_config,
+            config,
+            position.pair_index,
+            position.opened_at,
+            closed_at,
+        )?;
 
         let pnl = if position.direction == 0 {
             ((position.current_price as i128 - position.entry_price as i128) * position.size as i128 / position.entry_price as i128) as i64
@@ -22,9 +61,13 @@ pub mod close_position {
             ((position.entry_price as i128 - position.current_price as i128) * position.size as i128 / position.entry_price as i128) as i64
         };
 
+        let pnl = trading_account
+            .release_margin(position.size, pnl)
+            .ok_or(ErrorCode::MathOverflow)?;
+
         position.pnl = pnl;
         position.is_open = false;
-        position.closed_at = clock.unix_timestamp;
+        position.closed_at = closed_at;
 
         trading_account.total_pnl += pnl;
         if pnl > 0 {
@@ -49,4 +92,18 @@ pub mod close_position {
 pub enum ErrorCode {
     #[msg("Position is not open")]
     PositionNotOpen,
+    #[msg("Competition is not active")]
+    CompetitionNotActive,
+    #[msg("Competition has not started yet")]
+    CompetitionNotStarted,
+    #[msg("Trading account is not joined to this competition by the owner of the position")]
+    NotJoined,
+    #[msg("Math overflow")]
+    MathOverflow,
+    #[msg("Missing the price update, pair config or config account")]
+    MissingOracleAccounts,
+    #[msg("Position belongs to another wallet")]
+    NotPositionOwner,
+    #[msg("Position was opened in another competition or trading account")]
+    PositionAccountMismatch,
 }
\ No newline at end of file
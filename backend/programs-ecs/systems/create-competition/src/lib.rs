@@ -0,0 +1,144 @@
+use bolt_lang::*;
+use competition::{Competition, MAX_ALLOWED_PAIRS, MAX_NAME_LEN};
+
+declare_id!("L4bm4mebr8D6xFR4ZZUiJwGeUeuiKQ3k1soVN9YtRbZ");
+
+#[system]
+pub mod create_competition {
+
+    pub fn execute(ctx: Context<Components>, args: Vec<u8>) -> Result<Components> {
+        let args = CreateCompetitionArgs::try_from_slice(&args)
+            .map_err(|_| ErrorCode::InvalidArgs)?;
+        let competition = &mut ctx.accounts.competition;
+
+        check_create(competition, &args)?;
+
+        competition.authority = *ctx.accounts.authority.key;
+        competition.start_time = args.start_time;
+        competition.end_time = args.end_time;
+        competition.total_participants = 0;
+        competition.prize_pool = 0;
+        competition.is_active = true;
+        competition.name = args.name;
+        competition.entry_fee = args.entry_fee;
+        competition.starting_balance = args.starting_balance;
+        competition.allowed_pairs = args.allowed_pairs;
+        competition.allow_late_join = args.allow_late_join;
+
+        Ok(ctx.accounts)
+    }
+
+    #[system_input]
+    pub struct Components {
+        pub competition: Competition,
+    }
+
+}
+
+/// Each competition is a fresh entity, an existing one can't be overwritten
+pub fn check_create(competition: &Competition, args: &CreateCompetitionArgs) -> Result<()> {
+    require!(competition.authority == Pubkey::default(), ErrorCode::CompetitionAlreadyCreated);
+    require!(args.end_time > args.start_time, ErrorCode::InvalidTimeWindow);
+    require!(!args.name.is_empty() && args.name.len() <= MAX_NAME_LEN, ErrorCode::InvalidName);
+    require!(args.starting_balance > 0, ErrorCode::InvalidStartingBalance);
+    require!(args.allowed_pairs.len() <= MAX_ALLOWED_PAIRS, ErrorCode::TooManyPairs);
+    Ok(())
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct CreateCompetitionArgs {
+    pub name: String,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub entry_fee: u64,
+    pub starting_balance: u64,
+    pub allowed_pairs: Vec<u8>,
+    pub allow_late_join: bool,
+}
+
+#[error_code]
+pub enum ErrorCode {
+    #[msg("Invalid create competition arguments")]
+    InvalidArgs,
+    #[msg("Competition already created")]
+    CompetitionAlreadyCreated,
+    #[msg("End time must be after start time")]
+    InvalidTimeWindow,
+    #[msg("Competition name must be 1 to 50 characters")]
+    InvalidName,
+    #[msg("Starting balance must be greater than zero")]
+    InvalidStartingBalance,
+    #[msg("Too many allowed pairs")]
+    TooManyPairs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> CreateCompetitionArgs {
+        CreateCompetitionArgs {
+            name: "Weekly".to_string(),
+            start_time: 1_700_000_000,
+            end_time: 1_700_604_800,
+            entry_fee: 0,
+            starting_balance: 10_000_000_000,
+            allowed_pairs: vec![0, 1],
+            allow_late_join: false,
+        }
+    }
+
+    fn check(args: &CreateCompetitionArgs) -> Result<()> {
+        check_create(&Competition::default(), args)
+    }
+
+    #[test]
+    fn valid_competition_is_created() {
+        assert!(check(&args()).is_ok());
+    }
+
+    #[test]
+    fn existing_competition_is_not_overwritten() {
+        let competition = Competition {
+            authority: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_create(&competition, &args()).unwrap_err(),
+            ErrorCode::CompetitionAlreadyCreated.into()
+        );
+    }
+
+    #[test]
+    fn window_must_end_after_it_starts() {
+        let mut args = args();
+        args.end_time = args.start_time;
+        assert_eq!(check(&args).unwrap_err(), ErrorCode::InvalidTimeWindow.into());
+    }
+
+    #[test]
+    fn name_is_bounded() {
+        let mut args = args();
+        args.name = String::new();
+        assert_eq!(check(&args).unwrap_err(), ErrorCode::InvalidName.into());
+
+        args.name = "a".repeat(MAX_NAME_LEN);
+        assert!(check(&args).is_ok());
+        args.name.push('a');
+        assert_eq!(check(&args).unwrap_err(), ErrorCode::InvalidName.into());
+    }
+
+    #[test]
+    fn starting_balance_is_positive() {
+        let mut args = args();
+        args.starting_balance = 0;
+        assert_eq!(check(&args).unwrap_err(), ErrorCode::InvalidStartingBalance.into());
+    }
+
+    #[test]
+    fn allowed_pairs_are_bounded() {
+        let mut args = args();
+        args.allowed_pairs = (0..=MAX_ALLOWED_PAIRS as u8).collect();
+        assert_eq!(check(&args).unwrap_err(), ErrorCode::TooManyPairs.into());
+    }
+}
@@ -10,12 +10,22 @@ pub mod join_competition {
     pub fn execute(ctx: Context<Components>, _args: Vec<u8>) -> Result<Components> {
         let clock = Clock::get()?;
         let trading_account = &mut ctx.accounts.trading_account;
+        let competition_key = ctx.accounts.competition.key();
         let competition = &mut ctx.accounts.competition;
 
         require!(competition.is_active, ErrorCode::CompetitionNotActive);
+        require!(!competition.has_ended(clock.unix_timestamp), ErrorCode::CompetitionEnded);
+        require!(
+            competition.allow_late_join || !competition.has_started(clock.unix_timestamp),
+            ErrorCode::LateJoinNotAllowed
+        );
+
+        check_join(trading_account, ctx.accounts.authority.key)?;
 
         trading_account.owner = *ctx.accounts.authority.key;
-        trading_account.balance = 10000 * 1_000_000;
+        trading_account.competition = competition_key;
+        trading_account.joined = true;
+        trading_account.balance = competition.starting_balance;
         trading_account.total_pnl = 0;
         trading_account.total_trades = 0;
         trading_account.winning_trades = 0;
@@ -35,8 +45,64 @@ pub mod join_competition {
 
 }
 
+/// A trading account joins once, and only its owner can claim it
+pub fn check_join(trading_account: &TradingAccount, signer: &Pubkey) -> Result<()> {
+    require!(!trading_account.joined, ErrorCode::AlreadyJoined);
+    require!(
+        trading_account.owner == Pubkey::default() || trading_account.owner == *signer,
+        ErrorCode::NotAccountOwner
+    );
+    Ok(())
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("Competition is not active")]
     CompetitionNotActive,
+    #[msg("Competition has ended")]
+    CompetitionEnded,
+    #[msg("Competition has already started and doesn't accept late joiners")]
+    LateJoinNotAllowed,
+    #[msg("Trading account already joined a competition")]
+    AlreadyJoined,
+    #[msg("Trading account belongs to another wallet")]
+    NotAccountOwner,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fresh_account_can_join() {
+        let signer = Pubkey::new_unique();
+        assert!(check_join(&TradingAccount::default(), &signer).is_ok());
+    }
+
+    #[test]
+    fn rejoin_is_rejected() {
+        let signer = Pubkey::new_unique();
+        let trading_account = TradingAccount {
+            owner: signer,
+            competition: Pubkey::new_unique(),
+            joined: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            check_join(&trading_account, &signer).unwrap_err(),
+            ErrorCode::AlreadyJoined.into()
+        );
+    }
+
+    #[test]
+    fn other_wallet_cannot_claim_account() {
+        let trading_account = TradingAccount {
+            owner: Pubkey::new_unique(),
+            ..Default::default()
+        };
+        assert_eq!(
+            check_join(&trading_account, &Pubkey::new_unique()).unwrap_err(),
+            ErrorCode::NotAccountOwner.into()
+        );
+    }
 }
\ No newline at end of file
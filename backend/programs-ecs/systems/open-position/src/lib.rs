@@ -1,4 +1,5 @@
 use bolt_lang::*;
+use paper_trading::get_pair_price;
 use position::Position;
 use trading_account::TradingAccount;
 use competition::Competition;
@@ -11,25 +12,53 @@ pub mod open_position {
     pub fn execute(ctx: Context<Components>, args: Vec<u8>) -> Result<Components> {
         let args: OpenPositionArgs = OpenPositionArgs::try_from_slice(&args).unwrap();
         let clock = Clock::get()?;
+        let trading_account_key = ctx.accounts.trading_account.key();
 
         let position = &mut ctx.accounts.position;
         let trading_account = &mut ctx.accounts.trading_account;
         let competition = &ctx.accounts.competition;
 
         require!(competition.is_active, ErrorCode::CompetitionNotActive);
+        require!(competition.has_started(clock.unix_timestamp), ErrorCode::CompetitionNotStarted);
+        require!(!competition.has_ended(clock.unix_timestamp), ErrorCode::CompetitionEnded);
+        require!(competition.is_pair_allowed(args.pair_index), ErrorCode::PairNotAllowed);
+        require!(
+            trading_account.is_bound_to(ctx.accounts.authority.key, &competition.key()),
+            ErrorCode::NotJoined
+        );
+        require!(!position.is_open, ErrorCode::PositionAlreadyOpen);
+        // A closed position entity is only reused by its owner
+        require!(
+            position.owner == Pubkey::default() || position.owner == *ctx.accounts.authority.key,
+            ErrorCode::NotPositionOwner
+        );
+        require!(args.size > 0, ErrorCode::InvalidArgs);
+
+        // Remaining accounts: the Pyth price update, the pair config and the config of the
+        // paper trading program (feed id and oracle limits)
+        let [price_update, pair_config, config, ..] = ctx.remaining_accounts else {
+            return err!(ErrorCode::MissingOracleAccounts);
+        };
+        let entry_price = get_pair_price(price_update, pair_config, config, args.pair_index, 0, clock.unix_timestamp)?;
+
+        trading_account
+            .lock_margin(args.size)
+            .ok_or(ErrorCode::InsufficientBalance)?;
 
         position.owner = *ctx.accounts.authority.key;
         position.pair_index = args.pair_index;
         position.direction = args.direction;
-        position.entry_price = args.current_price;
+        position.entry_price = entry_price;
         position.size = args.size;
         position.take_profit = args.take_profit.unwrap_or(0);
         position.stop_loss = args.stop_loss.unwrap_or(0);
-        position.current_price = args.current_price;
+        position.current_price = entry_price;
         position.pnl = 0;
         position.is_open = true;
         position.opened_at = clock.unix_timestamp;
         position.closed_at = 0;
+        position.competition = competition.key();
+        position.trading_account = trading_account_key;
 
         trading_account.total_trades += 1;
 
@@ -49,7 +78,6 @@ pub mod open_position {
 pub struct OpenPositionArgs {
     pub pair_index: u8,
     pub direction: u8,
-    pub current_price: u64,
     pub size: u64,
     pub take_profit: Option<u64>,
     pub stop_loss: Option<u64>,
@@ -59,4 +87,22 @@ pub struct OpenPositionArgs {
 pub enum ErrorCode {
     #[msg("Competition is not active")]
     CompetitionNotActive,
+    #[msg("Pair is not traded in this competition")]
+    PairNotAllowed,
+    #[msg("Competition has not started yet")]
+    CompetitionNotStarted,
+    #[msg("Competition has ended")]
+    CompetitionEnded,
+    #[msg("Trading account is not joined to this competition by the signer")]
+    NotJoined,
+    #[msg("Position is already open")]
+    PositionAlreadyOpen,
+    #[msg("Invalid open position arguments")]
+    InvalidArgs,
+    #[msg("Position size is above the available balance")]
+    InsufficientBalance,
+    #[msg("Position belongs to another wallet")]
+    NotPositionOwner,
+    #[msg("Missing the price update, pair config or config account")]
+    MissingOracleAccounts,
 }
\ No newline at end of file
@@ -18,6 +18,8 @@ declare_id!("32S5nHLK93PNVJQZgd4PQY4v9tkiLU2j9bEbHhJN4CuL");
 #[system]
 pub mod settle_competition {
 
+    /// The positions still open at end_time are closed by close_position, priced at
+    /// end_time, so that their margin and PnL count in the final balances
     pub fn execute(ctx: Context<Components>, args: Vec<u8>) -> Result<Components> {
         let clock = Clock::get()?;
         let competition = &mut ctx.accounts.competition;
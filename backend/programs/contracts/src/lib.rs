@@ -1,17 +1,79 @@
 use anchor_lang::prelude::*;
 use bolt_lang::*;
 
+pub mod oracle;
+pub use oracle::*;
+
 declare_id!("b6NjCktqaB4KqTvsNmYTJ9KBfwMJ7Sh4hMJ2Xz26YR3");
 
+/// All the prices handled by the program have 6 decimals
+pub const PRICE_DECIMALS: i32 = 6;
+
+/// Max length of a pair symbol (ex: "SOL/USDT")
+pub const MAX_SYMBOL_LEN: usize = 16;
+
+/// Max decimals of a token (ETH, AVAX and LINK have 18)
+pub const MAX_TOKEN_DECIMALS: u8 = 18;
+
+/// Max leverage that can be configured on a pair
+pub const MAX_LEVERAGE: u8 = 100;
+
+/// Max number of backends in the executor whitelist (sizes the ProgramConfig)
+pub const MAX_EXECUTORS: usize = 10;
+
+/// Current layout version of the UserAccount (see migrate_user_account)
+/// 1: token decimals, limit orders, trade statistics and event sequence
+pub const USER_ACCOUNT_VERSION: u8 = 1;
+
+/// Current layout version of the PositionAccount (see migrate_position)
+/// 1: leverage, collateral, trailing stop and realized PnL
+pub const POSITION_ACCOUNT_VERSION: u8 = 1;
+
+/// Current layout version of the ProgramConfig (see migrate_config)
+/// 1: oracle limits, pause switch, halted pairs, admin roles and entry fee
+pub const CONFIG_VERSION: u8 = 1;
+
+/// Number of trades kept in a TradeHistory ring buffer
+pub const TRADE_HISTORY_LEN: usize = 64;
+
+/// Default oracle limits set at config initialization
+pub const DEFAULT_MAX_PRICE_AGE: u64 = 60;         // 60 seconds
+pub const DEFAULT_MAX_CONFIDENCE_BPS: u64 = 200;   // 2% of the price
+
+/// Entry fee set at config initialization (and back-filled by migrate_config)
+pub const DEFAULT_ENTRY_FEE_LAMPORTS: u64 = 100_000_000; // 0.1 SOL
+
 /// Account for MagicBlock Bolt - the user account usable in Ephemeral Rollups
 /// A user can have multiple accounts (one per trading pair)
 #[account]
 #[derive(Default, InitSpace)]
 pub struct UserAccount {
     pub owner: Pubkey,
-    pub pair_index: u8,          // Pair identifier (0=SOL/USDT, 1=BTC/USDT, etc.)
+    pub pair_index: u8,          // Pair identifier (see PairConfig)
     pub token_in_balance: u64,   // Balance of the input token (ex: USDT) - 6 decimals
     pub token_out_balance: u64,  // Balance of the output token (ex: SOL/BTC/ETH) - 9 decimals
+    pub token_in_decimals: u8,   // Decimals of token_in, copied from the PairConfig
+    pub token_out_decimals: u8,  // Decimals of token_out, copied from the PairConfig
+    pub total_positions: u64,
+    pub created_at: i64,
+    pub version: u8,             // Layout version - new fields are added after it
+    pub total_orders: u64,       // Limit orders placed, used as the order_id seed
+    pub realized_pnl: i64,       // token_in gained or lost on closed positions, net of fees
+    pub winning_trades: u64,     // Closes (full or partial) with a positive PnL
+    pub losing_trades: u64,      // Closes (full or partial) with a negative PnL
+    pub total_volume: u64,       // token_in notional of every buy, sell, open and close
+    pub fees_paid: u64,          // token_in paid in fees (liquidation fees)
+    pub event_sequence: u64,     // Sequence of the last event that moved token_in_balance
+    pub last_price_time: i64,    // publish_time of the newest price used, older ones are rejected
+}
+
+/// UserAccount layout before the decimals were stored (version 0)
+#[derive(AnchorDeserialize)]
+pub struct UserAccountV0 {
+    pub owner: Pubkey,
+    pub pair_index: u8,
+    pub token_in_balance: u64,
+    pub token_out_balance: u64,
     pub total_positions: u64,
     pub created_at: i64,
 }
@@ -31,15 +93,159 @@ pub struct PositionAccount {
     pub status: PositionStatus,
     pub opened_at: i64,
     pub closed_at: i64,
+    pub leverage: u8,                   // 1x to the max leverage of the pair
+    pub collateral: u64,                // token_in locked for the position (notional / leverage)
+    pub liquidation_price: u64,         // Price at which the position can be liquidated (6 decimals)
+    pub maintenance_margin_bps: u16,    // Maintenance margin of the pair when opened
+    pub trailing_mode: TrailingStopMode,
+    pub trailing_distance: u64,         // Price (6 decimals) or bps, depending on trailing_mode
+    pub high_water_mark: u64,           // Best price since the trailing stop was set (lowest for a SHORT)
+    pub close_price: u64,               // Price of the final close (6 decimals)
+    pub realized_pnl: i64,              // token_in realized by the partial and final closes
+    pub version: u8,                    // Layout version (see POSITION_ACCOUNT_VERSION)
+    pub rent_payer: Pubkey,             // Gets the rent back in close_position_account
+}
+
+/// Last TRADE_HISTORY_LEN trades of a user on a pair, oldest entries are overwritten
+/// Zero-copy so that the whole history is read in one RPC call
+#[account(zero_copy)]
+pub struct TradeHistory {
+    pub owner: Pubkey,
+    pub total_records: u64,      // Records ever written, the next one goes to total_records % LEN
+    pub records: [TradeRecord; TRADE_HISTORY_LEN],
+    pub pair_index: u8,
+    pub _padding: [u8; 7],
+}
+
+impl TradeHistory {
+    pub fn push(&mut self, record: TradeRecord) {
+        let slot = (self.total_records % TRADE_HISTORY_LEN as u64) as usize;
+        self.records[slot] = record;
+        self.total_records += 1;
+    }
+}
+
+/// Compact summary of a trade in a TradeHistory
+#[zero_copy]
+pub struct TradeRecord {
+    pub timestamp: i64,
+    pub position_id: u64,        // 0 for spot trades
+    pub amount: u64,             // token_out
+    pub price: u64,              // Trade or close price (6 decimals)
+    pub entry_price: u64,        // 0 for spot trades
+    pub realized_pnl: i64,       // token_in, 0 for spot trades
+    pub kind: u8,                // TradeRecordKind
+    pub _padding: [u8; 7],
+}
+
+/// Invitation of a wallet: its accounts are created without the entry fee
+#[account]
+#[derive(InitSpace)]
+pub struct FeeWaiver {
+    pub user: Pubkey,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+/// Resting limit order, turned into a PositionAccount by fill_order
+/// The collateral of the future position is reserved when the order is placed
+#[account]
+#[derive(InitSpace)]
+pub struct OrderAccount {
+    pub owner: Pubkey,
+    pub pair_index: u8,
+    pub order_id: u64,
+    pub side: PositionType,
+    pub amount_token_out: u64,   // Size of the position to open
+    pub limit_price: u64,        // Entry price of the position (6 decimals)
+    pub leverage: u8,
+    pub take_profit_price: u64,  // TP of the position, 0 = none
+    pub stop_loss_price: u64,    // SL of the position, 0 = none
+    pub reserved_collateral: u64, // token_in taken from the balance until fill or cancel
+    pub expires_at: i64,         // 0 = good until cancelled
+    pub status: OrderStatus,
+    pub created_at: i64,
 }
 
 /// Global configuration of the program with the admin whitelist
 #[account]
+#[derive(InitSpace)]
 pub struct ProgramConfig {
     pub authority: Pubkey,           // Super admin
     pub treasury: Pubkey,            // Wallet that receives the fees
+    #[max_len(MAX_EXECUTORS)]
     pub authorized_executors: Vec<Pubkey>, // Whitelist of authorized backends
     pub bump: u8,
+    pub max_price_age: u64,          // Max age of a price update (seconds)
+    pub max_confidence_bps: u64,     // Max confidence interval relative to the price (bps)
+    pub version: u8,                 // Layout version (see CONFIG_VERSION)
+    pub paused: bool,                // Stops every new trade, closes stay allowed
+    pub halted_pairs: [u8; 32],      // Bitmap of the pairs with trading halted (bit = pair_index)
+    pub pending_authority: Pubkey,   // Proposed by the authority, becomes it once accepted
+    pub pauser: Pubkey,              // set_pause and halt_pair
+    pub executor_manager: Pubkey,    // add_executor and remove_executor
+    pub fee_manager: Pubkey,         // update_treasury, set_entry_fee and the fee waivers
+    pub entry_fee_lamports: u64,     // Charged by initialize_account, 0 = free
+}
+
+impl ProgramConfig {
+    /// The authority holds every role, an unset role (default key) can't be signed for
+    pub fn has_role(&self, role: AdminRole, key: &Pubkey) -> bool {
+        let holder = match role {
+            AdminRole::Pauser => self.pauser,
+            AdminRole::ExecutorManager => self.executor_manager,
+            AdminRole::FeeManager => self.fee_manager,
+        };
+        *key == self.authority || *key == holder
+    }
+
+    pub fn is_pair_halted(&self, pair_index: u8) -> bool {
+        self.halted_pairs[pair_index as usize / 8] & (1 << (pair_index % 8)) != 0
+    }
+
+    pub fn set_pair_halted(&mut self, pair_index: u8, halted: bool) {
+        let bit = 1 << (pair_index % 8);
+        if halted {
+            self.halted_pairs[pair_index as usize / 8] |= bit;
+        } else {
+            self.halted_pairs[pair_index as usize / 8] &= !bit;
+        }
+    }
+}
+
+/// Configuration of a trading pair, managed by the admin
+/// One PDA per pair_index: adding a pair is a config transaction
+#[account]
+#[derive(InitSpace)]
+pub struct PairConfig {
+    pub pair_index: u8,
+    #[max_len(MAX_SYMBOL_LEN)]
+    pub symbol: String,          // ex: "SOL/USDT"
+    pub token_in_decimals: u8,   // ex: USDT - 6 decimals
+    pub token_out_decimals: u8,  // ex: SOL - 9 decimals
+    pub feed_id: [u8; 32],       // Pyth price feed id
+    pub min_order_size: u64,     // Min amount of token_out per order
+    pub max_order_size: u64,     // Max amount of token_out per order
+    pub enabled: bool,           // New accounts and trades are rejected when disabled
+    pub bump: u8,
+    pub max_leverage: u8,        // ex: 20 for 20x
+    pub maintenance_margin_bps: u16, // Min equity of a position relative to its notional
+    pub liquidation_fee_bps: u16,    // Share of the collateral paid to the liquidator
+}
+
+/// Parameters of a pair set by the admin
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PairParams {
+    pub symbol: String,
+    pub token_in_decimals: u8,
+    pub token_out_decimals: u8,
+    pub feed_id: [u8; 32],
+    pub min_order_size: u64,
+    pub max_order_size: u64,
+    pub enabled: bool,
+    pub max_leverage: u8,
+    pub maintenance_margin_bps: u16,
+    pub liquidation_fee_bps: u16,
 }
 
 #[program]
@@ -53,6 +259,16 @@ pub mod paper_trading {
         config.treasury = treasury;
         config.authorized_executors = Vec::new();
         config.bump = ctx.bumps.config;
+        config.max_price_age = DEFAULT_MAX_PRICE_AGE;
+        config.max_confidence_bps = DEFAULT_MAX_CONFIDENCE_BPS;
+        config.version = CONFIG_VERSION;
+        config.paused = false;
+        config.halted_pairs = [0; 32];
+        config.pending_authority = Pubkey::default();
+        config.pauser = Pubkey::default();
+        config.executor_manager = Pubkey::default();
+        config.fee_manager = Pubkey::default();
+        config.entry_fee_lamports = DEFAULT_ENTRY_FEE_LAMPORTS;
 
         emit!(ConfigInitialized {
             authority: config.authority,
@@ -62,6 +278,97 @@ pub mod paper_trading {
         Ok(())
     }
 
+    /// First step of an authority transfer, the new authority must accept it
+    /// Proposing the default key cancels a pending transfer
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.pending_authority = new_authority;
+
+        emit!(AuthorityProposed {
+            authority: config.authority,
+            pending_authority: new_authority,
+        });
+
+        Ok(())
+    }
+
+    /// Second step of an authority transfer, signed by the proposed authority
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_authority = config.authority;
+        config.authority = config.pending_authority;
+        config.pending_authority = Pubkey::default();
+
+        emit!(AuthorityTransferred {
+            previous_authority,
+            authority: config.authority,
+        });
+
+        Ok(())
+    }
+
+    /// Give an admin role to a key (the default key removes it)
+    pub fn set_role(ctx: Context<SetRole>, role: AdminRole, key: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        match role {
+            AdminRole::Pauser => config.pauser = key,
+            AdminRole::ExecutorManager => config.executor_manager = key,
+            AdminRole::FeeManager => config.fee_manager = key,
+        }
+
+        emit!(RoleUpdated { role, key });
+
+        Ok(())
+    }
+
+    /// Change the wallet that receives the fees
+    pub fn update_treasury(ctx: Context<UpdateTreasury>, treasury: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_treasury = config.treasury;
+        config.treasury = treasury;
+
+        emit!(TreasuryUpdated {
+            previous_treasury,
+            treasury,
+        });
+
+        Ok(())
+    }
+
+    /// Change the price of a new account (0 makes the entry free)
+    pub fn set_entry_fee(ctx: Context<UpdateEntryFee>, entry_fee_lamports: u64) -> Result<()> {
+        ctx.accounts.config.entry_fee_lamports = entry_fee_lamports;
+
+        emit!(EntryFeeUpdated { entry_fee_lamports });
+
+        Ok(())
+    }
+
+    /// Let a wallet create its accounts without paying the entry fee (ex: invited teams)
+    pub fn grant_fee_waiver(ctx: Context<GrantFeeWaiver>, user: Pubkey) -> Result<()> {
+        let fee_waiver = &mut ctx.accounts.fee_waiver;
+        fee_waiver.user = user;
+        fee_waiver.created_at = Clock::get()?.unix_timestamp;
+        fee_waiver.bump = ctx.bumps.fee_waiver;
+
+        emit!(FeeWaiverUpdated {
+            user,
+            waived: true,
+        });
+
+        Ok(())
+    }
+
+    /// Remove the waiver of a wallet, the rent goes back to the fee manager
+    pub fn revoke_fee_waiver(ctx: Context<RevokeFeeWaiver>) -> Result<()> {
+        emit!(FeeWaiverUpdated {
+            user: ctx.accounts.fee_waiver.user,
+            waived: false,
+        });
+
+        Ok(())
+    }
+
     /// Add an authorized backend executor to execute the TP/SL
     pub fn add_executor(ctx: Context<UpdateExecutors>, executor: Pubkey) -> Result<()> {
         let config = &mut ctx.accounts.config;
@@ -70,6 +377,10 @@ pub mod paper_trading {
             !config.authorized_executors.contains(&executor),
             ErrorCode::ExecutorAlreadyExists
         );
+        require!(
+            config.authorized_executors.len() < MAX_EXECUTORS,
+            ErrorCode::TooManyExecutors
+        );
 
         config.authorized_executors.push(executor);
 
@@ -89,16 +400,129 @@ pub mod paper_trading {
         Ok(())
     }
 
+    /// Bring the size of the config account to the current layout
+    /// The realloc is done by the ResizeConfig constraints, the authority pays the rent
+    /// A config of the first version must go through migrate_config instead, which also
+    /// back-fills the new fields
+    pub fn resize_config(ctx: Context<ResizeConfig>) -> Result<()> {
+        emit!(ConfigResized {
+            space: ctx.accounts.config.to_account_info().data_len() as u64,
+        });
+
+        Ok(())
+    }
+
+    /// Stop (or resume) every new trade, on all the pairs
+    /// Closes, TP/SL executions and liquidations stay allowed so that users can exit
+    pub fn set_pause(ctx: Context<UpdateTradingStatus>, paused: bool) -> Result<()> {
+        ctx.accounts.config.paused = paused;
+
+        emit!(PauseUpdated { paused });
+
+        Ok(())
+    }
+
+    /// Circuit breaker of a single pair, ex: during an incident on its oracle feed
+    /// Same rules as set_pause: only the trades and the openings are blocked
+    pub fn halt_pair(
+        ctx: Context<UpdateTradingStatus>,
+        pair_index: u8,
+        halted: bool,
+    ) -> Result<()> {
+        ctx.accounts.config.set_pair_halted(pair_index, halted);
+
+        emit!(PairHaltUpdated { pair_index, halted });
+
+        Ok(())
+    }
+
+    /// Register a new trading pair (ex: pair_index=3 for AVAX/USDT)
+    pub fn initialize_pair(
+        ctx: Context<InitializePair>,
+        pair_index: u8,
+        params: PairParams,
+    ) -> Result<()> {
+        validate_pair_params(&params)?;
+
+        let pair_config = &mut ctx.accounts.pair_config;
+        pair_config.pair_index = pair_index;
+        pair_config.bump = ctx.bumps.pair_config;
+        apply_pair_params(pair_config, params);
+
+        emit!(PairConfigured {
+            pair_index,
+            symbol: pair_config.symbol.clone(),
+            feed_id: pair_config.feed_id,
+            enabled: pair_config.enabled,
+        });
+
+        Ok(())
+    }
+
+    /// Update the parameters of an existing pair (feed, order sizes, enable/disable)
+    pub fn update_pair(ctx: Context<UpdatePair>, params: PairParams) -> Result<()> {
+        validate_pair_params(&params)?;
+
+        let pair_config = &mut ctx.accounts.pair_config;
+        apply_pair_params(pair_config, params);
+
+        emit!(PairConfigured {
+            pair_index: pair_config.pair_index,
+            symbol: pair_config.symbol.clone(),
+            feed_id: pair_config.feed_id,
+            enabled: pair_config.enabled,
+        });
+
+        Ok(())
+    }
+
+    /// Update the staleness and confidence limits applied to the oracle prices
+    pub fn set_oracle_limits(
+        ctx: Context<UpdateOracleConfig>,
+        max_price_age: u64,
+        max_confidence_bps: u64,
+    ) -> Result<()> {
+        require!(
+            max_price_age > 0 && max_confidence_bps > 0,
+            ErrorCode::InvalidOracleLimits
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.max_price_age = max_price_age;
+        config.max_confidence_bps = max_confidence_bps;
+
+        emit!(OracleLimitsUpdated {
+            max_price_age,
+            max_confidence_bps,
+        });
+
+        Ok(())
+    }
+
     /// Initialize a paper trading account for a user on a specific pair
-    /// The user pays an entry fee and receives mock tokens
+    /// The user pays the entry fee of the config (unless waived) and receives mock tokens
     /// The user can create multiple accounts (one per pair: SOL/USDT, BTC/USDT, etc.)
     pub fn initialize_account(
         ctx: Context<InitializeAccount>,
-        pair_index: u8,          // 0=SOL/USDT, 1=BTC/USDT, 2=ETH/USDT, etc.
-        entry_fee: u64,
+        pair_index: u8,          // Must be a pair registered in PairConfig
+        max_entry_fee: u64,      // Highest fee the user accepts to pay (lamports)
         initial_token_in: u64,   // How many token_in to give to the user (ex: 10,000 USDT)
+        token_in_decimals: u8,   // Must match the PairConfig (ex: USDT - 6)
+        token_out_decimals: u8,  // Must match the PairConfig (ex: SOL - 9)
     ) -> Result<()> {
-        require!(entry_fee >= 100_000_000, ErrorCode::EntryFeeTooLow); // Min 0.1 SOL
+        let entry_fee = if ctx.accounts.fee_waiver.is_some() {
+            0
+        } else {
+            ctx.accounts.config.entry_fee_lamports
+        };
+        require!(entry_fee <= max_entry_fee, ErrorCode::EntryFeeAboveMax);
+
+        let pair_config = &ctx.accounts.pair_config;
+        require!(
+            token_in_decimals == pair_config.token_in_decimals
+                && token_out_decimals == pair_config.token_out_decimals,
+            ErrorCode::DecimalsMismatch
+        );
 
         let user_account = &mut ctx.accounts.user_account;
         let clock = Clock::get()?;
@@ -107,45 +531,230 @@ pub mod paper_trading {
         user_account.pair_index = pair_index;
         user_account.token_in_balance = initial_token_in;
         user_account.token_out_balance = 0;
+        user_account.token_in_decimals = token_in_decimals;
+        user_account.token_out_decimals = token_out_decimals;
         user_account.total_positions = 0;
         user_account.created_at = clock.unix_timestamp;
+        user_account.version = USER_ACCOUNT_VERSION;
+
+        let mut trade_history = ctx.accounts.trade_history.load_init()?;
+        trade_history.owner = ctx.accounts.user.key();
+        trade_history.pair_index = pair_index;
+        drop(trade_history);
 
         // Transfer the fees to the treasury
-        let cpi_context = CpiContext::new(
-            ctx.accounts.system_program.to_account_info(),
-            anchor_lang::system_program::Transfer {
-                from: ctx.accounts.user.to_account_info(),
-                to: ctx.accounts.treasury.to_account_info(),
-            },
-        );
-        anchor_lang::system_program::transfer(cpi_context, entry_fee)?;
+        if entry_fee > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                anchor_lang::system_program::Transfer {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: ctx.accounts.treasury.to_account_info(),
+                },
+            );
+            anchor_lang::system_program::transfer(cpi_context, entry_fee)?;
+        }
 
         emit!(AccountInitialized {
             user: ctx.accounts.user.key(),
             pair_index,
             initial_token_in,
+            entry_fee,
             timestamp: clock.unix_timestamp,
         });
 
         Ok(())
     }
 
+    /// Bring a UserAccount created by the first version of the program to the current layout
+    /// Version 0 accounts have no decimals: they are back-filled from the PairConfig,
+    /// the fields appended after `version` start at zero
+    pub fn migrate_user_account(ctx: Context<MigrateUserAccount>) -> Result<()> {
+        let account_info = ctx.accounts.user_account.to_account_info();
+        let pair_config = &ctx.accounts.pair_config;
+
+        // Grow the account first so that every field of the current layout can be read
+        let space = 8 + UserAccount::INIT_SPACE;
+        grow_account(
+            &account_info,
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.system_program,
+            space,
+        )?;
+
+        let migrated = {
+            let data = account_info.try_borrow_data()?;
+            // Also checks the discriminator. The version byte of a version 0 account
+            // falls in the zeroed padding of its old allocation
+            let current = UserAccount::try_deserialize(&mut &data[..])?;
+
+            match current.version {
+                0 => {
+                    let legacy = UserAccountV0::deserialize(&mut &data[8..])?;
+                    UserAccount {
+                        owner: legacy.owner,
+                        pair_index: legacy.pair_index,
+                        token_in_balance: legacy.token_in_balance,
+                        token_out_balance: legacy.token_out_balance,
+                        token_in_decimals: pair_config.token_in_decimals,
+                        token_out_decimals: pair_config.token_out_decimals,
+                        total_positions: legacy.total_positions,
+                        created_at: legacy.created_at,
+                        version: USER_ACCOUNT_VERSION,
+                        ..Default::default()
+                    }
+                }
+                _ => return err!(ErrorCode::AccountAlreadyMigrated),
+            }
+        };
+
+        require!(
+            migrated.owner == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let mut data = account_info.try_borrow_mut_data()?;
+        migrated.try_serialize(&mut &mut data[..])?;
+
+        emit!(UserAccountMigrated {
+            user: migrated.owner,
+            pair_index: migrated.pair_index,
+            version: migrated.version,
+        });
+
+        Ok(())
+    }
+
+    /// Bring a PositionAccount created by the first version of the program to the current layout
+    /// Version 0 positions are 1x and locked their whole notional: the collateral is the
+    /// amount debited at the opening, the other margin fields are back-filled from the
+    /// entry and the PairConfig. The UserAccount must be migrated first
+    pub fn migrate_position(ctx: Context<MigratePosition>, position_id: u64) -> Result<()> {
+        let account_info = ctx.accounts.position_account.to_account_info();
+        let pair_config = &ctx.accounts.pair_config;
+
+        let space = 8 + PositionAccount::INIT_SPACE;
+        grow_account(
+            &account_info,
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.system_program,
+            space,
+        )?;
+
+        let migrated = {
+            let data = account_info.try_borrow_data()?;
+            // The fields added since version 0 are all after the old ones, they read as zero
+            let current = PositionAccount::try_deserialize(&mut &data[..])?;
+
+            match current.version {
+                0 => {
+                    // What the first version debited at the opening: amount * price / 10^6,
+                    // whatever the decimals of the pair
+                    let collateral = to_u64(
+                        (current.amount_token_out as u128) * (current.entry_price as u128)
+                            / 10u128.pow(PRICE_DECIMALS as u32),
+                    )?;
+                    let liquidation_price = liquidation_price(
+                        &current.position_type,
+                        current.entry_price,
+                        1,
+                        pair_config.maintenance_margin_bps,
+                    )?;
+                    PositionAccount {
+                        leverage: 1,
+                        collateral,
+                        liquidation_price,
+                        maintenance_margin_bps: pair_config.maintenance_margin_bps,
+                        version: POSITION_ACCOUNT_VERSION,
+                        rent_payer: current.owner,
+                        ..current
+                    }
+                }
+                _ => return err!(ErrorCode::AccountAlreadyMigrated),
+            }
+        };
+
+        require!(
+            migrated.owner == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let mut data = account_info.try_borrow_mut_data()?;
+        migrated.try_serialize(&mut &mut data[..])?;
+
+        emit!(PositionMigrated {
+            user: migrated.owner,
+            position_id,
+            version: migrated.version,
+        });
+
+        Ok(())
+    }
+
+    /// Bring the ProgramConfig deployed by the first version of the program to the current layout
+    /// Unlike resize_config, it works even when the old allocation can't be read anymore
+    /// The fields appended after `bump` read as zero: the oracle limits and the entry fee
+    /// get their defaults (0 would reject every price and make the entry free)
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let account_info = ctx.accounts.config.to_account_info();
+
+        let space = 8 + ProgramConfig::INIT_SPACE;
+        grow_account(
+            &account_info,
+            &ctx.accounts.authority.to_account_info(),
+            &ctx.accounts.system_program,
+            space,
+        )?;
+
+        let migrated = {
+            let data = account_info.try_borrow_data()?;
+            let current = ProgramConfig::try_deserialize(&mut &data[..])?;
+            require!(current.version == 0, ErrorCode::AccountAlreadyMigrated);
+            ProgramConfig {
+                version: CONFIG_VERSION,
+                max_price_age: DEFAULT_MAX_PRICE_AGE,
+                max_confidence_bps: DEFAULT_MAX_CONFIDENCE_BPS,
+                entry_fee_lamports: DEFAULT_ENTRY_FEE_LAMPORTS,
+                ..current
+            }
+        };
+
+        require!(
+            migrated.authority == ctx.accounts.authority.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let mut data = account_info.try_borrow_mut_data()?;
+        migrated.try_serialize(&mut &mut data[..])?;
+
+        emit!(ConfigMigrated {
+            version: migrated.version,
+        });
+
+        Ok(())
+    }
+
     /// Buy token_out with token_in (without TP/SL)
     /// Example: buy SOL with USDT (pair_index=0)
-    /// The price is passed as a parameter (calculated by the backend via Pyth or other oracle)
+    /// The price is read from the Pyth price update of the pair
     pub fn buy(
         ctx: Context<Trade>, 
         amount_token_out: u64,  // How many token_out to buy
-        price: u64,             // Current price (6 decimals) - ex: 150.50 USDT = 150_500_000
     ) -> Result<()> {
+        let clock = Clock::get()?;
         let user_account = &mut ctx.accounts.user_account;
+        let price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            user_account,
+            0,
+            &clock,
+        )?;
+        check_order_size(&ctx.accounts.pair_config, amount_token_out)?;
         
         // Calculate the cost in token_in
-        let cost_token_in = (amount_token_out as u128)
-            .checked_mul(price as u128)
-            .unwrap()
-            .checked_div(1_000_000)
-            .unwrap() as u64;
+        let cost_token_in = token_in_value(user_account, amount_token_out, price)?;
+        record_volume(user_account, cost_token_in)?;
 
         require!(
             user_account.token_in_balance >= cost_token_in,
@@ -156,11 +765,11 @@ pub mod paper_trading {
         user_account.token_in_balance = user_account
             .token_in_balance
             .checked_sub(cost_token_in)
-            .unwrap();
+            .ok_or(ErrorCode::MathOverflow)?;
         user_account.token_out_balance = user_account
             .token_out_balance
             .checked_add(amount_token_out)
-            .unwrap();
+            .ok_or(ErrorCode::MathOverflow)?;
 
         emit!(TradeExecuted {
             user: user_account.owner,
@@ -168,7 +777,22 @@ pub mod paper_trading {
             trade_type: TradeType::Buy,
             amount: amount_token_out,
             price,
-            timestamp: Clock::get()?.unix_timestamp,
+            token_in_amount: cost_token_in,
+            token_in_balance: user_account.token_in_balance,
+            token_out_balance: user_account.token_out_balance,
+            sequence: next_event_sequence(user_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.trade_history.load_mut()?.push(TradeRecord {
+            timestamp: clock.unix_timestamp,
+            position_id: 0,
+            amount: amount_token_out,
+            price,
+            entry_price: 0,
+            realized_pnl: 0,
+            kind: TradeRecordKind::Buy as u8,
+            _padding: [0; 7],
         });
 
         Ok(())
@@ -179,9 +803,18 @@ pub mod paper_trading {
     pub fn sell(
         ctx: Context<Trade>, 
         amount_token_out: u64,  // How many token_out to sell
-        price: u64,             // Current price (6 decimals)
     ) -> Result<()> {
+        let clock = Clock::get()?;
         let user_account = &mut ctx.accounts.user_account;
+        let price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            user_account,
+            0,
+            &clock,
+        )?;
+        check_order_size(&ctx.accounts.pair_config, amount_token_out)?;
 
         require!(
             user_account.token_out_balance >= amount_token_out,
@@ -189,21 +822,18 @@ pub mod paper_trading {
         );
         
         // Calculate how many token_in we receive
-        let received_token_in = (amount_token_out as u128)
-            .checked_mul(price as u128)
-            .unwrap()
-            .checked_div(1_000_000)
-            .unwrap() as u64;
+        let received_token_in = token_in_value(user_account, amount_token_out, price)?;
+        record_volume(user_account, received_token_in)?;
 
         // Update the balances
         user_account.token_out_balance = user_account
             .token_out_balance
             .checked_sub(amount_token_out)
-            .unwrap();
+            .ok_or(ErrorCode::MathOverflow)?;
         user_account.token_in_balance = user_account
             .token_in_balance
             .checked_add(received_token_in)
-            .unwrap();
+            .ok_or(ErrorCode::MathOverflow)?;
 
         emit!(TradeExecuted {
             user: user_account.owner,
@@ -211,157 +841,112 @@ pub mod paper_trading {
             trade_type: TradeType::Sell,
             amount: amount_token_out,
             price,
-            timestamp: Clock::get()?.unix_timestamp,
+            token_in_amount: received_token_in,
+            token_in_balance: user_account.token_in_balance,
+            token_out_balance: user_account.token_out_balance,
+            sequence: next_event_sequence(user_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        ctx.accounts.trade_history.load_mut()?.push(TradeRecord {
+            timestamp: clock.unix_timestamp,
+            position_id: 0,
+            amount: amount_token_out,
+            price,
+            entry_price: 0,
+            realized_pnl: 0,
+            kind: TradeRecordKind::Sell as u8,
+            _padding: [0; 7],
         });
 
         Ok(())
     }
 
-    /// Open a LONG position with TP/SL
-    /// Buy token_out and create a position monitored by the backend
+    /// Open a LONG position with optional TP/SL (0 = none)
+    /// Locks notional / leverage of token_in as collateral and creates a position
+    /// monitored by the backend
     pub fn open_long_position(
         ctx: Context<OpenPosition>,
         amount_token_out: u64,
-        entry_price: u64,        // Entry price provided by the backend
+        leverage: u8,            // 1x up to the max leverage of the pair
         take_profit_price: u64,
         stop_loss_price: u64,
     ) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
-        let position_account = &mut ctx.accounts.position_account;
-
-        // Check the consistency of the prices for a LONG
-        require!(
-            take_profit_price > entry_price,
-            ErrorCode::InvalidTakeProfitPrice
-        );
-        require!(
-            stop_loss_price < entry_price,
-            ErrorCode::InvalidStopLossPrice
-        );
-
-        // Calculate the cost
-        let cost_token_in = (amount_token_out as u128)
-            .checked_mul(entry_price as u128)
-            .unwrap()
-            .checked_div(1_000_000)
-            .unwrap() as u64;
+        let clock = Clock::get()?;
+        let entry_price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            &mut ctx.accounts.user_account,
+            0,
+            &clock,
+        )?;
 
-        require!(
-            user_account.token_in_balance >= cost_token_in,
-            ErrorCode::InsufficientBalance
-        );
+        check_tp_sl(
+            &PositionType::Long,
+            entry_price,
+            take_profit_price,
+            stop_loss_price,
+        )?;
 
-        // Deduct the token_in
-        user_account.token_in_balance = user_account
-            .token_in_balance
-            .checked_sub(cost_token_in)
-            .unwrap();
+        open_position_logic(
+            &mut ctx.accounts.position_account,
+            &mut ctx.accounts.user_account,
+            &ctx.accounts.pair_config,
+            PositionType::Long,
+            amount_token_out,
+            leverage,
+            entry_price,
+            take_profit_price,
+            stop_loss_price,
+            clock.unix_timestamp,
+        )
+    }
 
-        // Create the position
+    /// Open a SHORT position with TP/SL
+    /// The collateral is locked in token_in, exactly like a LONG
+    pub fn open_short_position(
+        ctx: Context<OpenPosition>,
+        amount_token_out: u64,
+        leverage: u8,
+        take_profit_price: u64,
+        stop_loss_price: u64,
+    ) -> Result<()> {
         let clock = Clock::get()?;
-        position_account.owner = user_account.owner;
-        position_account.pair_index = user_account.pair_index;
-        position_account.position_id = user_account.total_positions;
-        position_account.position_type = PositionType::Long;
-        position_account.amount_token_out = amount_token_out;
-        position_account.entry_price = entry_price;
-        position_account.take_profit_price = take_profit_price;
-        position_account.stop_loss_price = stop_loss_price;
-        position_account.status = PositionStatus::Active;
-        position_account.opened_at = clock.unix_timestamp;
-        position_account.closed_at = 0;
+        let entry_price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            &mut ctx.accounts.user_account,
+            0,
+            &clock,
+        )?;
 
-        user_account.total_positions += 1;
-
-        emit!(PositionOpened {
-            user: user_account.owner,
-            pair_index: user_account.pair_index,
-            position_id: position_account.position_id,
-            position_type: PositionType::Long,
-            amount: amount_token_out,
+        check_tp_sl(
+            &PositionType::Short,
             entry_price,
-            tp_price: take_profit_price,
-            sl_price: stop_loss_price,
-            timestamp: clock.unix_timestamp,
-        });
-
-        Ok(())
-    }
-
-    /// Open a SHORT position with TP/SL
-    pub fn open_short_position(
-        ctx: Context<OpenPosition>,
-        amount_token_out: u64,
-        entry_price: u64,
-        take_profit_price: u64,
-        stop_loss_price: u64,
-    ) -> Result<()> {
-        let user_account = &mut ctx.accounts.user_account;
-        let position_account = &mut ctx.accounts.position_account;
-
-        // Check the consistency of the prices for a SHORT (inverse of the LONG)
-        require!(
-            take_profit_price < entry_price,
-            ErrorCode::InvalidTakeProfitPrice
-        );
-        require!(
-            stop_loss_price > entry_price,
-            ErrorCode::InvalidStopLossPrice
-        );
-
-        let cost_token_in = (amount_token_out as u128)
-            .checked_mul(entry_price as u128)
-            .unwrap()
-            .checked_div(1_000_000)
-            .unwrap() as u64;
-
-        require!(
-            user_account.token_in_balance >= cost_token_in,
-            ErrorCode::InsufficientBalance
-        );
-
-        user_account.token_in_balance = user_account
-            .token_in_balance
-            .checked_sub(cost_token_in)
-            .unwrap();
-
-        // Create the short position
-        let clock = Clock::get()?;
-        position_account.owner = user_account.owner;
-        position_account.pair_index = user_account.pair_index;
-        position_account.position_id = user_account.total_positions;
-        position_account.position_type = PositionType::Short;
-        position_account.amount_token_out = amount_token_out;
-        position_account.entry_price = entry_price;
-        position_account.take_profit_price = take_profit_price;
-        position_account.stop_loss_price = stop_loss_price;
-        position_account.status = PositionStatus::Active;
-        position_account.opened_at = clock.unix_timestamp;
-        position_account.closed_at = 0;
-
-        user_account.total_positions += 1;
+            take_profit_price,
+            stop_loss_price,
+        )?;
 
-        emit!(PositionOpened {
-            user: user_account.owner,
-            pair_index: user_account.pair_index,
-            position_id: position_account.position_id,
-            position_type: PositionType::Short,
-            amount: amount_token_out,
+        open_position_logic(
+            &mut ctx.accounts.position_account,
+            &mut ctx.accounts.user_account,
+            &ctx.accounts.pair_config,
+            PositionType::Short,
+            amount_token_out,
+            leverage,
             entry_price,
-            tp_price: take_profit_price,
-            sl_price: stop_loss_price,
-            timestamp: clock.unix_timestamp,
-        });
-
-        Ok(())
+            take_profit_price,
+            stop_loss_price,
+            clock.unix_timestamp,
+        )
     }
 
     /// Execute a TP/SL automatically
     /// ONLY callable by authorized backends in the whitelist
-    pub fn execute_tp_sl(
-        ctx: Context<ExecuteTPSL>,
-        current_price: u64,  // Current price provided by the backend
-    ) -> Result<()> {
+    /// The TP/SL condition is checked against the Pyth price of the pair
+    pub fn execute_tp_sl(ctx: Context<ExecuteTPSL>) -> Result<()> {
         let config = &ctx.accounts.config;
         let position_account = &mut ctx.accounts.position_account;
         let user_account = &mut ctx.accounts.user_account;
@@ -377,28 +962,44 @@ pub mod paper_trading {
             ErrorCode::PositionNotActive
         );
 
+        let current_price = get_trade_price(
+            &ctx.accounts.price_update,
+            config,
+            &ctx.accounts.pair_config,
+            user_account,
+            position_account.opened_at,
+            &Clock::get()?,
+        )?;
+
         // Check that the pair_index corresponds
         require!(
             position_account.pair_index == user_account.pair_index,
             ErrorCode::PairMismatch
         );
 
-        // Check that the TP or SL condition is met
+        // Check that the TP or SL condition is met, a price of 0 means no TP/SL
+        let tp_set = position_account.take_profit_price != 0;
+        let sl_set = position_account.stop_loss_price != 0;
+        let stop_reason = if position_account.trailing_mode == TrailingStopMode::None {
+            CloseReason::StopLoss
+        } else {
+            CloseReason::TrailingStop
+        };
         let close_reason = match position_account.position_type {
             PositionType::Long => {
-                if current_price >= position_account.take_profit_price {
+                if tp_set && current_price >= position_account.take_profit_price {
                     CloseReason::TakeProfit
-                } else if current_price <= position_account.stop_loss_price {
-                    CloseReason::StopLoss
+                } else if sl_set && current_price <= position_account.stop_loss_price {
+                    stop_reason
                 } else {
                     return Err(ErrorCode::ConditionNotMet.into());
                 }
             }
             PositionType::Short => {
-                if current_price <= position_account.take_profit_price {
+                if tp_set && current_price <= position_account.take_profit_price {
                     CloseReason::TakeProfit
-                } else if current_price >= position_account.stop_loss_price {
-                    CloseReason::StopLoss
+                } else if sl_set && current_price >= position_account.stop_loss_price {
+                    stop_reason
                 } else {
                     return Err(ErrorCode::ConditionNotMet.into());
                 }
@@ -409,18 +1010,18 @@ pub mod paper_trading {
         close_position_logic(
             position_account,
             user_account,
+            &mut *ctx.accounts.trade_history.load_mut()?,
             current_price,
             close_reason,
+            0,
+            Clock::get()?.unix_timestamp,
         )?;
 
         Ok(())
     }
 
-    /// Close a manually active position
-    pub fn close_position(
-        ctx: Context<ClosePositionManual>,
-        current_price: u64,  // Current price provided by the backend
-    ) -> Result<()> {
+    /// Close a manually active position at the Pyth price of the pair
+    pub fn close_position(ctx: Context<ClosePositionManual>) -> Result<()> {
         let position_account = &mut ctx.accounts.position_account;
         let user_account = &mut ctx.accounts.user_account;
 
@@ -440,245 +1041,1960 @@ pub mod paper_trading {
             ErrorCode::PairMismatch
         );
 
+        let current_price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            user_account,
+            position_account.opened_at,
+            &Clock::get()?,
+        )?;
+
         close_position_logic(
             position_account,
             user_account,
+            &mut *ctx.accounts.trade_history.load_mut()?,
             current_price,
             CloseReason::Manual,
+            0,
+            Clock::get()?.unix_timestamp,
         )?;
 
         Ok(())
     }
-}
 
-// ============= HELPER FUNCTIONS =============
+    /// Close part of an active position at the Pyth price of the pair
+    /// The PnL of the closed amount is realized, the rest stays open
+    /// Closing the whole amount is the same as close_position
+    pub fn close_position_partial(
+        ctx: Context<ClosePositionManual>,
+        amount_token_out: u64,  // How many token_out to close
+    ) -> Result<()> {
+        let position_account = &mut ctx.accounts.position_account;
+        let user_account = &mut ctx.accounts.user_account;
 
-/// Common logic to close a position
-fn close_position_logic(
-    position_account: &mut PositionAccount,
-    user_account: &mut UserAccount,
-    current_price: u64,
-    close_reason: CloseReason,
-) -> Result<()> {
-    let clock = Clock::get()?;
-
-    match position_account.position_type {
-        PositionType::Long => {
-            // Calculate the current value
-            let current_value = (position_account.amount_token_out as u128)
-                .checked_mul(current_price as u128)
-                .unwrap()
-                .checked_div(1_000_000)
-                .unwrap() as u64;
-
-            // Return the token_in to the user
-            user_account.token_in_balance = user_account
-                .token_in_balance
-                .checked_add(current_value)
-                .unwrap();
+        require!(
+            position_account.status == PositionStatus::Active,
+            ErrorCode::PositionNotActive
+        );
+
+        require!(
+            position_account.owner == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+
+        // Check that the pair_index corresponds
+        require!(
+            position_account.pair_index == user_account.pair_index,
+            ErrorCode::PairMismatch
+        );
+
+        let clock = Clock::get()?;
+        let current_price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            user_account,
+            position_account.opened_at,
+            &clock,
+        )?;
+
+        let mut trade_history = ctx.accounts.trade_history.load_mut()?;
+        if amount_token_out == position_account.amount_token_out {
+            close_position_logic(
+                position_account,
+                user_account,
+                &mut trade_history,
+                current_price,
+                CloseReason::Manual,
+                0,
+                clock.unix_timestamp,
+            )?;
+        } else {
+            reduce_position_logic(
+                position_account,
+                user_account,
+                &ctx.accounts.pair_config,
+                &mut trade_history,
+                amount_token_out,
+                current_price,
+                clock.unix_timestamp,
+            )?;
         }
-        PositionType::Short => {
-            let entry_value = (position_account.amount_token_out as u128)
-                .checked_mul(position_account.entry_price as u128)
-                .unwrap()
-                .checked_div(1_000_000)
-                .unwrap() as u64;
-
-            let current_value = (position_account.amount_token_out as u128)
-                .checked_mul(current_price as u128)
-                .unwrap()
-                .checked_div(1_000_000)
-                .unwrap() as u64;
-
-            if entry_value > current_value {
-                let profit = entry_value - current_value;
-                user_account.token_in_balance = user_account
-                    .token_in_balance
-                    .checked_add(entry_value)
-                    .unwrap()
-                    .checked_add(profit)
-                    .unwrap();
-            } else {
-                let loss = current_value - entry_value;
-                user_account.token_in_balance = user_account
-                    .token_in_balance
-                    .checked_add(entry_value)
-                    .unwrap()
-                    .checked_sub(loss)
-                    .unwrap();
+
+        Ok(())
+    }
+
+    /// Move the TP/SL of an active position, signed by its owner
+    /// The new prices are checked against the current Pyth price (a stop can be moved
+    /// past the entry price), 0 clears a leg
+    pub fn update_tp_sl(
+        ctx: Context<UpdateTpSl>,
+        take_profit_price: u64,
+        stop_loss_price: u64,
+    ) -> Result<()> {
+        let position_account = &mut ctx.accounts.position_account;
+
+        require!(
+            position_account.status == PositionStatus::Active,
+            ErrorCode::PositionNotActive
+        );
+        require!(
+            position_account.owner == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let clock = Clock::get()?;
+        let current_price = get_oracle_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            &clock,
+        )?;
+        check_tp_sl(
+            &position_account.position_type,
+            current_price,
+            take_profit_price,
+            stop_loss_price,
+        )?;
+
+        position_account.take_profit_price = take_profit_price;
+        position_account.stop_loss_price = stop_loss_price;
+
+        emit!(PositionUpdated {
+            user: position_account.owner,
+            pair_index: position_account.pair_index,
+            position_id: position_account.position_id,
+            tp_price: take_profit_price,
+            sl_price: stop_loss_price,
+            current_price,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Set a trailing stop on an active position, signed by its owner
+    /// The stop loss follows the best price at `distance` (a price or bps of the price)
+    /// and replaces the current stop loss. TrailingStopMode::None removes the trailing
+    pub fn set_trailing_stop(
+        ctx: Context<UpdateTpSl>,
+        mode: TrailingStopMode,
+        distance: u64,
+    ) -> Result<()> {
+        let position_account = &mut ctx.accounts.position_account;
+
+        require!(
+            position_account.status == PositionStatus::Active,
+            ErrorCode::PositionNotActive
+        );
+        require!(
+            position_account.owner == ctx.accounts.user.key(),
+            ErrorCode::Unauthorized
+        );
+
+        let clock = Clock::get()?;
+        let current_price = get_oracle_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            &clock,
+        )?;
+
+        position_account.trailing_mode = mode.clone();
+        if mode == TrailingStopMode::None {
+            // The last stop loss stays in place as a static stop
+            position_account.trailing_distance = 0;
+            position_account.high_water_mark = 0;
+        } else {
+            let valid_distance = match mode {
+                TrailingStopMode::Absolute => distance > 0 && distance < current_price,
+                _ => distance > 0 && distance < 10_000,
             };
+            require!(valid_distance, ErrorCode::InvalidTrailingStop);
+
+            position_account.trailing_distance = distance;
+            position_account.high_water_mark = current_price;
+            position_account.stop_loss_price = trailing_stop_price(position_account, current_price)?;
         }
+
+        emit!(TrailingStopUpdated {
+            user: position_account.owner,
+            pair_index: position_account.pair_index,
+            position_id: position_account.position_id,
+            mode,
+            high_water_mark: position_account.high_water_mark,
+            sl_price: position_account.stop_loss_price,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
     }
 
-    position_account.status = PositionStatus::Closed;
-    position_account.closed_at = clock.unix_timestamp;
+    /// Ratchet the trailing stop of a position when the price moved in its favor
+    /// Permissionless: the stop can only get closer to the price
+    pub fn update_trailing_stop(ctx: Context<UpdateTrailingStop>) -> Result<()> {
+        let position_account = &mut ctx.accounts.position_account;
 
-    emit!(PositionClosed {
-        user: position_account.owner,
-        pair_index: position_account.pair_index,
-        position_id: position_account.position_id,
-        close_price: current_price,
-        close_reason,
-        timestamp: clock.unix_timestamp,
-    });
+        require!(
+            position_account.status == PositionStatus::Active,
+            ErrorCode::PositionNotActive
+        );
+        require!(
+            position_account.trailing_mode != TrailingStopMode::None,
+            ErrorCode::InvalidTrailingStop
+        );
 
-    Ok(())
-}
+        let clock = Clock::get()?;
+        let current_price = get_oracle_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            &ctx.accounts.pair_config,
+            &clock,
+        )?;
 
-// ============= CONTEXTS =============
+        let improved = match position_account.position_type {
+            PositionType::Long => current_price > position_account.high_water_mark,
+            PositionType::Short => current_price < position_account.high_water_mark,
+        };
+        require!(improved, ErrorCode::ConditionNotMet);
 
-#[derive(Accounts)]
-pub struct InitializeConfig<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + 32 + 32 + 4 + (32 * 10) + 1, // authority + treasury + vec_len + 10 executors max + bump
-        seeds = [b"config"],
-        bump
-    )]
-    pub config: Account<'info, ProgramConfig>,
+        position_account.high_water_mark = current_price;
+        let stop_loss_price = trailing_stop_price(position_account, current_price)?;
+        let tighter = match position_account.position_type {
+            PositionType::Long => stop_loss_price > position_account.stop_loss_price,
+            PositionType::Short => {
+                position_account.stop_loss_price == 0
+                    || stop_loss_price < position_account.stop_loss_price
+            }
+        };
+        if tighter {
+            position_account.stop_loss_price = stop_loss_price;
+        }
 
-    #[account(mut)]
-    pub authority: Signer<'info>,
+        emit!(TrailingStopUpdated {
+            user: position_account.owner,
+            pair_index: position_account.pair_index,
+            position_id: position_account.position_id,
+            mode: position_account.trailing_mode.clone(),
+            high_water_mark: current_price,
+            sl_price: position_account.stop_loss_price,
+            timestamp: clock.unix_timestamp,
+        });
 
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct UpdateExecutors<'info> {
-    #[account(
-        mut,
-        seeds = [b"config"],
-        bump = config.bump,
-        has_one = authority @ ErrorCode::Unauthorized
-    )]
-    pub config: Account<'info, ProgramConfig>,
+    /// Liquidate a position once the oracle price crosses its liquidation price
+    /// Permissionless: any keeper can call it and receives the liquidation fee (in mock
+    /// token_in) on its own account of the pair
+    pub fn liquidate_position(ctx: Context<LiquidatePosition>) -> Result<()> {
+        let position_account = &mut ctx.accounts.position_account;
+        let user_account = &mut ctx.accounts.user_account;
+        let liquidator_account = &mut ctx.accounts.liquidator_account;
+        let pair_config = &ctx.accounts.pair_config;
 
-    pub authority: Signer<'info>,
-}
+        require!(
+            position_account.status == PositionStatus::Active,
+            ErrorCode::PositionNotActive
+        );
+
+        let clock = Clock::get()?;
+        let current_price = get_trade_price(
+            &ctx.accounts.price_update,
+            &ctx.accounts.config,
+            pair_config,
+            user_account,
+            position_account.opened_at,
+            &clock,
+        )?;
+
+        let liquidatable = match position_account.position_type {
+            PositionType::Long => current_price <= position_account.liquidation_price,
+            PositionType::Short => current_price >= position_account.liquidation_price,
+        };
+        require!(liquidatable, ErrorCode::PositionNotLiquidatable);
+
+        let liquidation_fee = to_u64(
+            (position_account.collateral as u128) * (pair_config.liquidation_fee_bps as u128)
+                / 10_000,
+        )?;
+        let (_, liquidation_fee) = close_position_logic(
+            position_account,
+            user_account,
+            &mut *ctx.accounts.trade_history.load_mut()?,
+            current_price,
+            CloseReason::Liquidation,
+            liquidation_fee,
+            clock.unix_timestamp,
+        )?;
+
+        liquidator_account.token_in_balance = liquidator_account
+            .token_in_balance
+            .checked_add(liquidation_fee)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(PositionLiquidated {
+            user: position_account.owner,
+            liquidator: ctx.accounts.liquidator.key(),
+            pair_index: position_account.pair_index,
+            position_id: position_account.position_id,
+            liquidation_price: position_account.liquidation_price,
+            close_price: current_price,
+            liquidation_fee,
+            liquidator_token_in_balance: liquidator_account.token_in_balance,
+            liquidator_sequence: next_event_sequence(liquidator_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Place a limit order that opens a position once the oracle crosses the limit price
+    /// LONG: filled when the price is at or below the limit, SHORT: at or above
+    /// The collateral is reserved at the limit price, TP/SL are optional (0 = none)
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_limit_order(
+        ctx: Context<PlaceLimitOrder>,
+        side: PositionType,
+        amount_token_out: u64,
+        limit_price: u64,
+        leverage: u8,
+        take_profit_price: u64,
+        stop_loss_price: u64,
+        expires_at: i64,         // 0 = good until cancelled
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+        let user_account = &mut ctx.accounts.user_account;
+        let order_account = &mut ctx.accounts.order_account;
+
+        require!(limit_price > 0, ErrorCode::InvalidLimitPrice);
+        require!(
+            expires_at == 0 || expires_at > clock.unix_timestamp,
+            ErrorCode::InvalidOrderExpiry
+        );
+        check_tp_sl(&side, limit_price, take_profit_price, stop_loss_price)?;
+
+        let collateral = position_collateral(
+            user_account,
+            &ctx.accounts.pair_config,
+            amount_token_out,
+            leverage,
+            limit_price,
+        )?;
+        require!(
+            user_account.token_in_balance >= collateral,
+            ErrorCode::InsufficientCollateral
+        );
+
+        // Reserve the collateral until the order is filled or cancelled
+        user_account.token_in_balance = user_account
+            .token_in_balance
+            .checked_sub(collateral)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        order_account.owner = user_account.owner;
+        order_account.pair_index = user_account.pair_index;
+        order_account.order_id = user_account.total_orders;
+        order_account.side = side.clone();
+        order_account.amount_token_out = amount_token_out;
+        order_account.limit_price = limit_price;
+        order_account.leverage = leverage;
+        order_account.take_profit_price = take_profit_price;
+        order_account.stop_loss_price = stop_loss_price;
+        order_account.reserved_collateral = collateral;
+        order_account.expires_at = expires_at;
+        order_account.status = OrderStatus::Open;
+        order_account.created_at = clock.unix_timestamp;
+
+        user_account.total_orders += 1;
+
+        emit!(OrderPlaced {
+            user: order_account.owner,
+            pair_index: order_account.pair_index,
+            order_id: order_account.order_id,
+            side,
+            amount: amount_token_out,
+            limit_price,
+            leverage,
+            reserved_collateral: collateral,
+            expires_at,
+            token_in_balance: user_account.token_in_balance,
+            sequence: next_event_sequence(user_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Cancel an open limit order, release its reserved collateral and return its rent
+    /// to the owner. Anyone can clean up an order once it has expired
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        let order_account = &ctx.accounts.order_account;
+        let user_account = &mut ctx.accounts.user_account;
+
+        require!(
+            order_account.status == OrderStatus::Open,
+            ErrorCode::OrderNotOpen
+        );
+
+        let clock = Clock::get()?;
+        let expired = order_account.expires_at != 0 && clock.unix_timestamp >= order_account.expires_at;
+        require!(
+            ctx.accounts.user.key() == order_account.owner || expired,
+            ErrorCode::Unauthorized
+        );
+
+        user_account.token_in_balance = user_account
+            .token_in_balance
+            .checked_add(order_account.reserved_collateral)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(OrderCancelled {
+            user: order_account.owner,
+            pair_index: order_account.pair_index,
+            order_id: order_account.order_id,
+            released_collateral: order_account.reserved_collateral,
+            token_in_balance: user_account.token_in_balance,
+            sequence: next_event_sequence(user_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Fill a limit order whose limit price is crossed by the Pyth price of the pair
+    /// ONLY callable by authorized backends in the whitelist, the executor pays the
+    /// rent of the position and gets it back when the position account is closed.
+    /// The position is opened at the limit price and the order account is closed
+    pub fn fill_order(ctx: Context<FillOrder>) -> Result<()> {
+        let config = &ctx.accounts.config;
+        let order_account = &ctx.accounts.order_account;
+        let user_account = &mut ctx.accounts.user_account;
+
+        require!(
+            config.authorized_executors.contains(&ctx.accounts.executor.key()),
+            ErrorCode::UnauthorizedExecutor
+        );
+        require!(
+            order_account.status == OrderStatus::Open,
+            ErrorCode::OrderNotOpen
+        );
+
+        let clock = Clock::get()?;
+        require!(
+            order_account.expires_at == 0 || clock.unix_timestamp < order_account.expires_at,
+            ErrorCode::OrderExpired
+        );
+
+        // A price from before the order was placed can't fill it
+        let current_price = get_trade_price(
+            &ctx.accounts.price_update,
+            config,
+            &ctx.accounts.pair_config,
+            user_account,
+            order_account.created_at,
+            &clock,
+        )?;
+        let crossed = match order_account.side {
+            PositionType::Long => current_price <= order_account.limit_price,
+            PositionType::Short => current_price >= order_account.limit_price,
+        };
+        require!(crossed, ErrorCode::LimitPriceNotReached);
+
+        // Release the reservation, open_position_logic locks the collateral again
+        user_account.token_in_balance = user_account
+            .token_in_balance
+            .checked_add(order_account.reserved_collateral)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        open_position_logic(
+            &mut ctx.accounts.position_account,
+            user_account,
+            &ctx.accounts.pair_config,
+            order_account.side.clone(),
+            order_account.amount_token_out,
+            order_account.leverage,
+            order_account.limit_price,
+            order_account.take_profit_price,
+            order_account.stop_loss_price,
+            clock.unix_timestamp,
+        )?;
+
+        let position_account = &mut ctx.accounts.position_account;
+        position_account.rent_payer = ctx.accounts.executor.key();
+
+        emit!(OrderFilled {
+            user: order_account.owner,
+            pair_index: order_account.pair_index,
+            order_id: order_account.order_id,
+            position_id: position_account.position_id,
+            fill_price: order_account.limit_price,
+            oracle_price: current_price,
+            token_in_balance: user_account.token_in_balance,
+            sequence: next_event_sequence(user_account),
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /// Create the TradeHistory of a user on a pair
+    /// Only needed for accounts created before initialize_account did it
+    pub fn initialize_trade_history(ctx: Context<InitializeTradeHistory>) -> Result<()> {
+        let mut trade_history = ctx.accounts.trade_history.load_init()?;
+        trade_history.owner = ctx.accounts.user.key();
+        trade_history.pair_index = ctx.accounts.user_account.pair_index;
+
+        Ok(())
+    }
+
+    /// Give the rent of a closed position back to whoever paid it: the owner, or the
+    /// executor for a position opened by fill_order
+    /// The closes of the position are already in the TradeHistory
+    pub fn close_position_account(ctx: Context<ClosePositionAccount>) -> Result<()> {
+        let position_account = &ctx.accounts.position_account;
+
+        emit!(PositionAccountClosed {
+            user: position_account.owner,
+            pair_index: position_account.pair_index,
+            position_id: position_account.position_id,
+        });
+
+        Ok(())
+    }
+}
+
+// ============= HELPER FUNCTIONS =============
+
+/// Realloc an account of an older layout to `space` bytes (zero-filled)
+/// The payer tops up the rent, nothing is done if the account is already large enough
+fn grow_account<'info>(
+    account_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &Program<'info, System>,
+    space: usize,
+) -> Result<()> {
+    if account_info.data_len() >= space {
+        return Ok(());
+    }
+
+    let missing_rent = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(account_info.lamports());
+    if missing_rent > 0 {
+        let cpi_context = CpiContext::new(
+            system_program.to_account_info(),
+            anchor_lang::system_program::Transfer {
+                from: payer.clone(),
+                to: account_info.clone(),
+            },
+        );
+        anchor_lang::system_program::transfer(cpi_context, missing_rent)?;
+    }
+    account_info.realloc(space, true)?;
+
+    Ok(())
+}
+
+/// Check the pair parameters provided by the admin
+fn validate_pair_params(params: &PairParams) -> Result<()> {
+    require!(
+        !params.symbol.is_empty() && params.symbol.len() <= MAX_SYMBOL_LEN,
+        ErrorCode::InvalidPairParams
+    );
+    require!(
+        params.token_in_decimals <= MAX_TOKEN_DECIMALS
+            && params.token_out_decimals <= MAX_TOKEN_DECIMALS,
+        ErrorCode::InvalidPairParams
+    );
+    require!(
+        params.min_order_size > 0 && params.min_order_size <= params.max_order_size,
+        ErrorCode::InvalidPairParams
+    );
+    require!(
+        params.max_leverage >= 1 && params.max_leverage <= MAX_LEVERAGE,
+        ErrorCode::InvalidPairParams
+    );
+    // A position at max leverage must not be liquidatable as soon as it is opened
+    require!(
+        (params.maintenance_margin_bps as u64) < 10_000 / params.max_leverage as u64,
+        ErrorCode::InvalidPairParams
+    );
+    require!(
+        params.liquidation_fee_bps <= 10_000,
+        ErrorCode::InvalidPairParams
+    );
+
+    Ok(())
+}
+
+fn apply_pair_params(pair_config: &mut PairConfig, params: PairParams) {
+    pair_config.symbol = params.symbol;
+    pair_config.token_in_decimals = params.token_in_decimals;
+    pair_config.token_out_decimals = params.token_out_decimals;
+    pair_config.feed_id = params.feed_id;
+    pair_config.min_order_size = params.min_order_size;
+    pair_config.max_order_size = params.max_order_size;
+    pair_config.enabled = params.enabled;
+    pair_config.max_leverage = params.max_leverage;
+    pair_config.maintenance_margin_bps = params.maintenance_margin_bps;
+    pair_config.liquidation_fee_bps = params.liquidation_fee_bps;
+}
+
+/// Check that an order amount (token_out) is within the pair limits
+fn check_order_size(pair_config: &PairConfig, amount_token_out: u64) -> Result<()> {
+    require!(
+        amount_token_out >= pair_config.min_order_size
+            && amount_token_out <= pair_config.max_order_size,
+        ErrorCode::OrderSizeOutOfRange
+    );
+
+    Ok(())
+}
+
+/// Value in token_in of an amount of token_out at a price (PRICE_DECIMALS)
+/// value = amount * price * 10^in_decimals / (10^out_decimals * 10^PRICE_DECIMALS)
+fn token_in_value(user_account: &UserAccount, amount_token_out: u64, price: u64) -> Result<u64> {
+    let numerator = (amount_token_out as u128)
+        .checked_mul(price as u128)
+        .and_then(|value| value.checked_mul(10u128.pow(user_account.token_in_decimals as u32)))
+        .ok_or(ErrorCode::MathOverflow)?;
+    let denominator =
+        10u128.pow(user_account.token_out_decimals as u32 + PRICE_DECIMALS as u32);
+
+    to_u64(numerator / denominator)
+}
+
+/// Narrow an intermediate u128 result, failing instead of truncating
+fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Check that the TP/SL of a position are on the right side of its entry price
+/// A price of 0 means no TP/SL
+fn check_tp_sl(
+    position_type: &PositionType,
+    entry_price: u64,
+    take_profit_price: u64,
+    stop_loss_price: u64,
+) -> Result<()> {
+    let (tp_valid, sl_valid) = match position_type {
+        PositionType::Long => (
+            take_profit_price > entry_price,
+            stop_loss_price < entry_price,
+        ),
+        PositionType::Short => (
+            take_profit_price < entry_price,
+            stop_loss_price > entry_price,
+        ),
+    };
+    require!(
+        take_profit_price == 0 || tp_valid,
+        ErrorCode::InvalidTakeProfitPrice
+    );
+    require!(
+        stop_loss_price == 0 || sl_valid,
+        ErrorCode::InvalidStopLossPrice
+    );
+
+    Ok(())
+}
+
+/// Stop loss of a trailing stop for a best price
+/// LONG: below the best price, SHORT: above it
+fn trailing_stop_price(position_account: &PositionAccount, best_price: u64) -> Result<u64> {
+    let distance = match position_account.trailing_mode {
+        TrailingStopMode::Absolute => position_account.trailing_distance,
+        _ => to_u64(
+            (best_price as u128) * (position_account.trailing_distance as u128) / 10_000,
+        )?,
+    };
+
+    let stop_loss_price = match position_account.position_type {
+        PositionType::Long => best_price.checked_sub(distance),
+        PositionType::Short => best_price.checked_add(distance),
+    };
+    stop_loss_price.ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Collateral locked by a position: notional / leverage of token_in
+/// Checks the order size and the leverage against the pair
+fn position_collateral(
+    user_account: &UserAccount,
+    pair_config: &PairConfig,
+    amount_token_out: u64,
+    leverage: u8,
+    entry_price: u64,
+) -> Result<u64> {
+    check_order_size(pair_config, amount_token_out)?;
+    require!(
+        leverage >= 1 && leverage <= pair_config.max_leverage,
+        ErrorCode::InvalidLeverage
+    );
+
+    // Only the margin is locked, the position is exposed on the full notional
+    let notional = token_in_value(user_account, amount_token_out, entry_price)?;
+    let collateral = notional / leverage as u64;
+    require!(collateral > 0, ErrorCode::OrderSizeOutOfRange);
+
+    Ok(collateral)
+}
+
+/// Common logic to open a position
+/// Debits the collateral (notional / leverage) and fills the position account
+/// Long and short share the same accounting: only token_in moves, the direction
+/// only changes the sign of the PnL at close
+#[allow(clippy::too_many_arguments)]
+pub fn open_position_logic(
+    position_account: &mut PositionAccount,
+    user_account: &mut UserAccount,
+    pair_config: &PairConfig,
+    position_type: PositionType,
+    amount_token_out: u64,
+    leverage: u8,
+    entry_price: u64,
+    take_profit_price: u64,
+    stop_loss_price: u64,
+    timestamp: i64,
+) -> Result<()> {
+    let collateral = position_collateral(
+        user_account,
+        pair_config,
+        amount_token_out,
+        leverage,
+        entry_price,
+    )?;
+    require!(
+        user_account.token_in_balance >= collateral,
+        ErrorCode::InsufficientCollateral
+    );
+
+    let liquidation_price = liquidation_price(
+        &position_type,
+        entry_price,
+        leverage,
+        pair_config.maintenance_margin_bps,
+    )?;
+
+    // Deduct the collateral
+    user_account.token_in_balance = user_account
+        .token_in_balance
+        .checked_sub(collateral)
+        .ok_or(ErrorCode::MathOverflow)?;
+    record_volume(
+        user_account,
+        token_in_value(user_account, amount_token_out, entry_price)?,
+    )?;
+
+    // Create the position
+    position_account.owner = user_account.owner;
+    position_account.pair_index = user_account.pair_index;
+    position_account.position_id = user_account.total_positions;
+    position_account.position_type = position_type.clone();
+    position_account.amount_token_out = amount_token_out;
+    position_account.entry_price = entry_price;
+    position_account.take_profit_price = take_profit_price;
+    position_account.stop_loss_price = stop_loss_price;
+    position_account.status = PositionStatus::Active;
+    position_account.opened_at = timestamp;
+    position_account.closed_at = 0;
+    position_account.leverage = leverage;
+    position_account.collateral = collateral;
+    position_account.liquidation_price = liquidation_price;
+    position_account.maintenance_margin_bps = pair_config.maintenance_margin_bps;
+    position_account.trailing_mode = TrailingStopMode::None;
+    position_account.trailing_distance = 0;
+    position_account.high_water_mark = 0;
+    position_account.close_price = 0;
+    position_account.realized_pnl = 0;
+    position_account.version = POSITION_ACCOUNT_VERSION;
+    // The owner pays the rent of the opens, fill_order hands it to the executor
+    position_account.rent_payer = user_account.owner;
+
+    user_account.total_positions += 1;
+
+    emit!(PositionOpened {
+        user: user_account.owner,
+        pair_index: user_account.pair_index,
+        position_id: position_account.position_id,
+        position_type,
+        amount: amount_token_out,
+        entry_price,
+        tp_price: take_profit_price,
+        sl_price: stop_loss_price,
+        leverage,
+        collateral,
+        liquidation_price,
+        token_in_balance: user_account.token_in_balance,
+        sequence: next_event_sequence(user_account),
+        timestamp,
+    });
+
+    Ok(())
+}
+
+/// Price at which the equity of a position (collateral + PnL) falls to the maintenance margin
+/// LONG: entry * (1 - 1/leverage + maintenance), SHORT: entry * (1 + 1/leverage - maintenance)
+fn liquidation_price(
+    position_type: &PositionType,
+    entry_price: u64,
+    leverage: u8,
+    maintenance_margin_bps: u16,
+) -> Result<u64> {
+    let initial_margin_bps = 10_000 / leverage as u128;
+    let maintenance_margin_bps = maintenance_margin_bps as u128;
+
+    let ratio_bps = match position_type {
+        PositionType::Long => 10_000 - initial_margin_bps + maintenance_margin_bps,
+        PositionType::Short => 10_000 + initial_margin_bps - maintenance_margin_bps,
+    };
+
+    to_u64((entry_price as u128) * ratio_bps / 10_000)
+}
+
+/// Realize the PnL of `amount_token_out` of a position backed by `collateral`
+/// The collateral is returned with the PnL on the closed notional
+/// Bankruptcy rule: the loss is capped at the collateral, so a close never fails on
+/// a losing position and never takes more than the locked margin
+/// Returns the amount of token_in credited to the user and the realized PnL
+fn settle_position(
+    position_account: &PositionAccount,
+    user_account: &mut UserAccount,
+    amount_token_out: u64,
+    collateral: u64,
+    current_price: u64,
+) -> Result<(u64, i64)> {
+    let entry_value =
+        token_in_value(user_account, amount_token_out, position_account.entry_price)? as i128;
+    let current_value = token_in_value(user_account, amount_token_out, current_price)? as i128;
+
+    let pnl = match position_account.position_type {
+        PositionType::Long => current_value - entry_value,
+        PositionType::Short => entry_value - current_value,
+    };
+
+    // Return the collateral +/- the PnL to the user, nothing once the loss exceeds it
+    let equity = (collateral as i128 + pnl).max(0);
+    let payout = u64::try_from(equity).map_err(|_| ErrorCode::MathOverflow)?;
+    user_account.token_in_balance = user_account
+        .token_in_balance
+        .checked_add(payout)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Statistics use the PnL after the bankruptcy cap, i.e. what the balance really moved
+    let realized_pnl = i64::try_from(equity - collateral as i128)
+        .map_err(|_| ErrorCode::MathOverflow)?;
+    user_account.realized_pnl = user_account
+        .realized_pnl
+        .checked_add(realized_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+    if realized_pnl > 0 {
+        user_account.winning_trades += 1;
+    } else if realized_pnl < 0 {
+        user_account.losing_trades += 1;
+    }
+    record_volume(user_account, current_value as u64)?;
+
+    Ok((payout, realized_pnl))
+}
+
+/// Increment the event sequence of the account and return it
+fn next_event_sequence(user_account: &mut UserAccount) -> u64 {
+    user_account.event_sequence += 1;
+    user_account.event_sequence
+}
+
+/// Add the token_in notional of a trade to the volume of the account
+fn record_volume(user_account: &mut UserAccount, notional: u64) -> Result<()> {
+    user_account.total_volume = user_account
+        .total_volume
+        .checked_add(notional)
+        .ok_or(ErrorCode::MathOverflow)?;
+    Ok(())
+}
+
+/// Summary of a (partial) close of a position for the TradeHistory
+fn position_trade_record(
+    position_account: &PositionAccount,
+    amount_token_out: u64,
+    close_price: u64,
+    realized_pnl: i64,
+    timestamp: i64,
+) -> TradeRecord {
+    let kind = match position_account.position_type {
+        PositionType::Long => TradeRecordKind::Long,
+        PositionType::Short => TradeRecordKind::Short,
+    };
+
+    TradeRecord {
+        timestamp,
+        position_id: position_account.position_id,
+        amount: amount_token_out,
+        price: close_price,
+        entry_price: position_account.entry_price,
+        realized_pnl,
+        kind: kind as u8,
+        _padding: [0; 7],
+    }
+}
+
+/// Common logic to close a position
+/// `fee` (liquidations) is taken from what is left of the collateral before the close is reported
+/// Returns the amount of token_in credited to the user (see settle_position) and the fee taken
+pub fn close_position_logic(
+    position_account: &mut PositionAccount,
+    user_account: &mut UserAccount,
+    trade_history: &mut TradeHistory,
+    current_price: u64,
+    close_reason: CloseReason,
+    fee: u64,
+    timestamp: i64,
+) -> Result<(u64, u64)> {
+    let (payout, realized_pnl) = settle_position(
+        position_account,
+        user_account,
+        position_account.amount_token_out,
+        position_account.collateral,
+        current_price,
+    )?;
+
+    // The fee is taken from what is left of the collateral
+    let fee = fee.min(payout);
+    user_account.token_in_balance = user_account
+        .token_in_balance
+        .checked_sub(fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user_account.fees_paid = user_account
+        .fees_paid
+        .checked_add(fee)
+        .ok_or(ErrorCode::MathOverflow)?;
+    user_account.realized_pnl = user_account
+        .realized_pnl
+        .checked_sub(fee as i64)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let net_pnl = realized_pnl
+        .checked_sub(fee as i64)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    position_account.status = PositionStatus::Closed;
+    position_account.closed_at = timestamp;
+    position_account.close_price = current_price;
+    position_account.realized_pnl = position_account
+        .realized_pnl
+        .checked_add(net_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(PositionClosed {
+        user: position_account.owner,
+        pair_index: position_account.pair_index,
+        position_id: position_account.position_id,
+        close_price: current_price,
+        close_reason,
+        position_type: position_account.position_type.clone(),
+        amount: position_account.amount_token_out,
+        entry_price: position_account.entry_price,
+        realized_pnl: net_pnl,
+        token_in_balance: user_account.token_in_balance,
+        sequence: next_event_sequence(user_account),
+        timestamp,
+    });
+
+    trade_history.push(position_trade_record(
+        position_account,
+        position_account.amount_token_out,
+        current_price,
+        net_pnl,
+        timestamp,
+    ));
+
+    Ok((payout - fee, fee))
+}
+
+/// Close a fraction of a position, it stays Active with the remaining size
+/// The collateral is released in proportion of the closed amount, the remaining size
+/// can't fall below the min order size of the pair (close the whole position instead)
+/// Returns the amount of token_in credited to the user (see settle_position)
+pub fn reduce_position_logic(
+    position_account: &mut PositionAccount,
+    user_account: &mut UserAccount,
+    pair_config: &PairConfig,
+    trade_history: &mut TradeHistory,
+    amount_token_out: u64,
+    current_price: u64,
+    timestamp: i64,
+) -> Result<u64> {
+    require!(
+        amount_token_out > 0 && amount_token_out < position_account.amount_token_out,
+        ErrorCode::InvalidCloseAmount
+    );
+    require!(
+        position_account.amount_token_out - amount_token_out >= pair_config.min_order_size,
+        ErrorCode::RemainingSizeTooSmall
+    );
+
+    let released_collateral = to_u64(
+        (position_account.collateral as u128) * (amount_token_out as u128)
+            / (position_account.amount_token_out as u128),
+    )?;
+    let (payout, realized_pnl) = settle_position(
+        position_account,
+        user_account,
+        amount_token_out,
+        released_collateral,
+        current_price,
+    )?;
+
+    position_account.amount_token_out -= amount_token_out;
+    position_account.collateral -= released_collateral;
+    position_account.realized_pnl = position_account
+        .realized_pnl
+        .checked_add(realized_pnl)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    emit!(PositionReduced {
+        user: position_account.owner,
+        pair_index: position_account.pair_index,
+        position_id: position_account.position_id,
+        closed_amount: amount_token_out,
+        remaining_amount: position_account.amount_token_out,
+        close_price: current_price,
+        realized_pnl,
+        token_in_balance: user_account.token_in_balance,
+        sequence: next_event_sequence(user_account),
+        timestamp,
+    });
+
+    trade_history.push(position_trade_record(
+        position_account,
+        amount_token_out,
+        current_price,
+        realized_pnl,
+        timestamp,
+    ));
+
+    Ok(payout)
+}
+
+// ============= CONTEXTS =============
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + ProgramConfig::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateExecutors<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::ExecutorManager, &executor_manager.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub executor_manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.pending_authority == new_authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetRole<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEntryFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::FeeManager, &fee_manager.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub fee_manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(user: Pubkey)]
+pub struct GrantFeeWaiver<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::FeeManager, &fee_manager.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = fee_manager,
+        space = 8 + FeeWaiver::INIT_SPACE,
+        seeds = [b"fee_waiver", user.as_ref()],
+        bump
+    )]
+    pub fee_waiver: Account<'info, FeeWaiver>,
+
+    #[account(mut)]
+    pub fee_manager: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeFeeWaiver<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::FeeManager, &fee_manager.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        close = fee_manager,
+        seeds = [b"fee_waiver", fee_waiver.user.as_ref()],
+        bump = fee_waiver.bump,
+    )]
+    pub fee_waiver: Account<'info, FeeWaiver>,
+
+    #[account(mut)]
+    pub fee_manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTreasury<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::FeeManager, &fee_manager.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub fee_manager: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ResizeConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized,
+        constraint = config.version == CONFIG_VERSION @ ErrorCode::AccountNotMigrated,
+        realloc = 8 + ProgramConfig::INIT_SPACE,
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTradingStatus<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.has_role(AdminRole::Pauser, &pauser.key()) @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub pauser: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateOracleConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(pair_index: u8)]
+pub struct InitializePair<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + PairConfig::INIT_SPACE,
+        seeds = [b"pair".as_ref(), &[pair_index]],
+        bump
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePair<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"pair".as_ref(), &[pair_config.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(position_id: u64)]
+pub struct MigratePosition<'info> {
+    /// CHECK: Older layout, deserialized and rewritten in migrate_position
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            user.key().as_ref(),
+            &[pair_config.pair_index],
+            position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        owner = crate::ID,
+    )]
+    pub position_account: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [
+            b"user",
+            user.key().as_ref(),
+            &[pair_config.pair_index]
+        ],
+        bump,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[pair_config.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    /// CHECK: Older layout, deserialized and rewritten in migrate_config
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump,
+        owner = crate::ID,
+    )]
+    pub config: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateUserAccount<'info> {
+    /// CHECK: Older layout, deserialized and rewritten in migrate_user_account
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            user.key().as_ref(),
+            &[pair_config.pair_index]
+        ],
+        bump,
+        owner = crate::ID,
+    )]
+    pub user_account: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[pair_config.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(pair_index: u8, max_entry_fee: u64, initial_token_in: u64)]
+pub struct InitializeAccount<'info> {
+    #[account(
+        init,
+        payer = user,
+        space = 8 + UserAccount::INIT_SPACE,
+        seeds = [
+            b"user", 
+            user.key().as_ref(),
+            &[pair_index]  // Include the pair in the seeds
+        ],
+        bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + std::mem::size_of::<TradeHistory>(),
+        seeds = [
+            b"history",
+            user.key().as_ref(),
+            &[pair_index]
+        ],
+        bump
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[pair_index]],
+        bump = pair_config.bump,
+        constraint = pair_config.enabled @ ErrorCode::PairDisabled
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Treasury wallet that receives fees - validated against config.treasury
+    #[account(mut, constraint = treasury.key() == config.treasury)]
+    pub treasury: SystemAccount<'info>,
+
+    /// Only for invited wallets, waives the entry fee
+    #[account(
+        seeds = [b"fee_waiver", user.key().as_ref()],
+        bump = fee_waiver.bump,
+    )]
+    pub fee_waiver: Option<Account<'info, FeeWaiver>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Trade<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"user", 
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"history",
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = !config.paused @ ErrorCode::TradingPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[user_account.pair_index]],
+        bump = pair_config.bump,
+        constraint = pair_config.enabled @ ErrorCode::PairDisabled,
+        constraint = !config.is_pair_halted(pair_config.pair_index) @ ErrorCode::PairHalted
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct OpenPosition<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"user", 
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + PositionAccount::INIT_SPACE,
+        seeds = [
+            b"position",
+            user.key().as_ref(),
+            &[user_account.pair_index],
+            user_account.total_positions.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = !config.paused @ ErrorCode::TradingPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[user_account.pair_index]],
+        bump = pair_config.bump,
+        constraint = pair_config.enabled @ ErrorCode::PairDisabled,
+        constraint = !config.is_pair_halted(pair_config.pair_index) @ ErrorCode::PairHalted
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteTPSL<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index],
+            position_account.position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = position_account.version == POSITION_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user", 
+            position_account.owner.as_ref(),
+            &[position_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"history",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index]
+        ],
+        bump,
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+
+    // Closing stays possible on a disabled pair
+    #[account(
+        seeds = [b"pair".as_ref(), &[position_account.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    /// The backend executor (must be in the whitelist)
+    pub executor: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePositionManual<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            user.key().as_ref(),
+            &[position_account.pair_index],
+            position_account.position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = position_account.version == POSITION_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user", 
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"history",
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    // Closing stays possible on a disabled pair
+    #[account(
+        seeds = [b"pair".as_ref(), &[position_account.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTpSl<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            user.key().as_ref(),
+            &[position_account.pair_index],
+            position_account.position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = position_account.version == POSITION_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    // Protecting a position stays possible on a disabled pair
+    #[account(
+        seeds = [b"pair".as_ref(), &[position_account.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTrailingStop<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index],
+            position_account.position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = position_account.version == POSITION_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[position_account.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+}
+
+#[derive(Accounts)]
+pub struct LiquidatePosition<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"position",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index],
+            position_account.position_id.to_le_bytes().as_ref()
+        ],
+        bump,
+        constraint = position_account.version == POSITION_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub position_account: Account<'info, PositionAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"history",
+            position_account.owner.as_ref(),
+            &[position_account.pair_index]
+        ],
+        bump,
+    )]
+    pub trade_history: AccountLoader<'info, TradeHistory>,
+
+    #[account(
+        seeds = [b"pair".as_ref(), &[position_account.pair_index]],
+        bump = pair_config.bump,
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    /// Account of the liquidator on the same pair, receives the liquidation fee
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            liquidator.key().as_ref(),
+            &[position_account.pair_index]
+        ],
+        bump,
+        constraint = liquidator_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub liquidator_account: Account<'info, UserAccount>,
+
+    /// Any keeper, except the owner of the position
+    #[account(constraint = liquidator.key() != position_account.owner @ ErrorCode::SelfLiquidation)]
+    pub liquidator: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct PlaceLimitOrder<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            user.key().as_ref(),
+            &[user_account.pair_index]
+        ],
+        bump,
+        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized,
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
+    )]
+    pub user_account: Account<'info, UserAccount>,
 
-#[derive(Accounts)]
-#[instruction(pair_index: u8, entry_fee: u64, initial_token_in: u64)]
-pub struct InitializeAccount<'info> {
     #[account(
         init,
         payer = user,
-        space = 8 + std::mem::size_of::<UserAccount>(),
+        space = 8 + OrderAccount::INIT_SPACE,
         seeds = [
-            b"user", 
+            b"order",
             user.key().as_ref(),
-            &[pair_index]  // Include the pair in the seeds
+            &[user_account.pair_index],
+            user_account.total_orders.to_le_bytes().as_ref()
         ],
         bump
     )]
-    pub user_account: Account<'info, UserAccount>,
+    pub order_account: Account<'info, OrderAccount>,
 
     #[account(
         seeds = [b"config"],
         bump = config.bump,
+        constraint = !config.paused @ ErrorCode::TradingPaused
     )]
     pub config: Account<'info, ProgramConfig>,
 
+    #[account(
+        seeds = [b"pair".as_ref(), &[user_account.pair_index]],
+        bump = pair_config.bump,
+        constraint = pair_config.enabled @ ErrorCode::PairDisabled,
+        constraint = !config.is_pair_halted(pair_config.pair_index) @ ErrorCode::PairHalted
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
-    /// CHECK: Treasury wallet that receives fees - validated against config.treasury
-    #[account(mut, constraint = treasury.key() == config.treasury)]
-    pub treasury: SystemAccount<'info>,
-
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct Trade<'info> {
+pub struct CancelOrder<'info> {
     #[account(
         mut,
+        close = owner,
         seeds = [
-            b"user", 
-            user.key().as_ref(),
-            &[user_account.pair_index]
+            b"order",
+            owner.key().as_ref(),
+            &[order_account.pair_index],
+            order_account.order_id.to_le_bytes().as_ref()
+        ],
+        bump,
+    )]
+    pub order_account: Account<'info, OrderAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            owner.key().as_ref(),
+            &[order_account.pair_index]
         ],
         bump,
-        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
     )]
     pub user_account: Account<'info, UserAccount>,
 
+    /// Owner of the order, receives the rent back
+    #[account(mut)]
+    pub owner: SystemAccount<'info>,
+
+    /// The owner, or anyone once the order has expired
     pub user: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct OpenPosition<'info> {
+pub struct FillOrder<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = !config.paused @ ErrorCode::TradingPaused
+    )]
+    pub config: Account<'info, ProgramConfig>,
+
     #[account(
         mut,
+        close = owner,
         seeds = [
-            b"user", 
-            user.key().as_ref(),
-            &[user_account.pair_index]
+            b"order",
+            owner.key().as_ref(),
+            &[order_account.pair_index],
+            order_account.order_id.to_le_bytes().as_ref()
+        ],
+        bump,
+    )]
+    pub order_account: Account<'info, OrderAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            order_account.owner.as_ref(),
+            &[order_account.pair_index]
         ],
         bump,
-        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized
+        constraint = user_account.version == USER_ACCOUNT_VERSION @ ErrorCode::AccountNotMigrated
     )]
     pub user_account: Account<'info, UserAccount>,
 
     #[account(
         init,
-        payer = user,
-        space = 8 + std::mem::size_of::<PositionAccount>(),
+        payer = executor,
+        space = 8 + PositionAccount::INIT_SPACE,
         seeds = [
             b"position",
-            user.key().as_ref(),
-            &[user_account.pair_index],
+            order_account.owner.as_ref(),
+            &[order_account.pair_index],
             user_account.total_positions.to_le_bytes().as_ref()
         ],
         bump
     )]
     pub position_account: Account<'info, PositionAccount>,
 
+    #[account(
+        seeds = [b"pair".as_ref(), &[order_account.pair_index]],
+        bump = pair_config.bump,
+        constraint = pair_config.enabled @ ErrorCode::PairDisabled,
+        constraint = !config.is_pair_halted(pair_config.pair_index) @ ErrorCode::PairHalted
+    )]
+    pub pair_config: Account<'info, PairConfig>,
+
+    /// Pyth price update of the pair (owner and feed id are checked)
+    pub price_update: Account<'info, PriceUpdateV2>,
+
+    /// Owner of the order, receives the rent of the order back
     #[account(mut)]
-    pub user: Signer<'info>,
+    pub owner: SystemAccount<'info>,
+
+    /// The backend executor (must be in the whitelist), pays the rent of the position
+    #[account(mut)]
+    pub executor: Signer<'info>,
 
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ExecuteTPSL<'info> {
+pub struct InitializeTradeHistory<'info> {
     #[account(
-        seeds = [b"config"],
-        bump = config.bump,
-    )]
-    pub config: Account<'info, ProgramConfig>,
-
-    #[account(
-        mut,
         seeds = [
-            b"position",
-            position_account.owner.as_ref(),
-            &[position_account.pair_index],
-            position_account.position_id.to_le_bytes().as_ref()
+            b"user",
+            user.key().as_ref(),
+            &[user_account.pair_index]
         ],
         bump,
+        constraint = user_account.owner == user.key() @ ErrorCode::Unauthorized,
     )]
-    pub position_account: Account<'info, PositionAccount>,
+    pub user_account: Account<'info, UserAccount>,
 
     #[account(
-        mut,
+        init,
+        payer = user,
+        space = 8 + std::mem::size_of::<TradeHistory>(),
         seeds = [
-            b"user", 
-            position_account.owner.as_ref(),
-            &[position_account.pair_index]
+            b"history",
+            user.key().as_ref(),
+            &[user_account.pair_index]
         ],
-        bump,
+        bump
     )]
-    pub user_account: Account<'info, UserAccount>,
+    pub trade_history: AccountLoader<'info, TradeHistory>,
 
-    /// The backend executor (must be in the whitelist)
-    pub executor: Signer<'info>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ClosePositionManual<'info> {
+pub struct ClosePositionAccount<'info> {
     #[account(
         mut,
+        close = rent_payer,
         seeds = [
             b"position",
             user.key().as_ref(),
@@ -686,19 +3002,13 @@ pub struct ClosePositionManual<'info> {
             position_account.position_id.to_le_bytes().as_ref()
         ],
         bump,
+        constraint = position_account.status == PositionStatus::Closed @ ErrorCode::PositionNotClosed
     )]
     pub position_account: Account<'info, PositionAccount>,
 
-    #[account(
-        mut,
-        seeds = [
-            b"user", 
-            user.key().as_ref(),
-            &[user_account.pair_index]
-        ],
-        bump,
-    )]
-    pub user_account: Account<'info, UserAccount>,
+    /// Payer of the position rent (the user, or the executor of a filled order)
+    #[account(mut, address = position_account.rent_payer)]
+    pub rent_payer: SystemAccount<'info>,
 
     pub user: Signer<'info>,
 }
@@ -719,6 +3029,31 @@ pub enum PositionStatus {
     Closed,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, Default, InitSpace)]
+pub enum TrailingStopMode {
+    #[default]
+    None,
+    Absolute,                    // Distance is a price (6 decimals)
+    Bps,                         // Distance is in bps of the best price
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum OrderStatus {
+    Open,
+    Filled,
+    Cancelled,
+}
+
+/// Kind of a TradeRecord, stored as u8
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TradeRecordKind {
+    Buy,
+    Sell,
+    Long,                        // Closed LONG position
+    Short,                       // Closed SHORT position
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
 pub enum TradeType {
     Buy,
@@ -730,6 +3065,16 @@ pub enum CloseReason {
     TakeProfit,
     StopLoss,
     Manual,
+    Liquidation,
+    TrailingStop,
+}
+
+/// Delegated admin roles of the ProgramConfig (the authority holds them all)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AdminRole {
+    Pauser,
+    ExecutorManager,
+    FeeManager,
 }
 
 // ============= EVENTS =============
@@ -740,6 +3085,41 @@ pub struct ConfigInitialized {
     pub treasury: Pubkey,
 }
 
+#[event]
+pub struct AuthorityProposed {
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+}
+
+#[event]
+pub struct AuthorityTransferred {
+    pub previous_authority: Pubkey,
+    pub authority: Pubkey,
+}
+
+#[event]
+pub struct RoleUpdated {
+    pub role: AdminRole,
+    pub key: Pubkey,
+}
+
+#[event]
+pub struct EntryFeeUpdated {
+    pub entry_fee_lamports: u64,
+}
+
+#[event]
+pub struct FeeWaiverUpdated {
+    pub user: Pubkey,
+    pub waived: bool,
+}
+
+#[event]
+pub struct TreasuryUpdated {
+    pub previous_treasury: Pubkey,
+    pub treasury: Pubkey,
+}
+
 #[event]
 pub struct ExecutorAdded {
     pub executor: Pubkey,
@@ -750,14 +3130,64 @@ pub struct ExecutorRemoved {
     pub executor: Pubkey,
 }
 
+#[event]
+pub struct ConfigResized {
+    pub space: u64,
+}
+
+#[event]
+pub struct PairConfigured {
+    pub pair_index: u8,
+    pub symbol: String,
+    pub feed_id: [u8; 32],
+    pub enabled: bool,
+}
+
+#[event]
+pub struct PauseUpdated {
+    pub paused: bool,
+}
+
+#[event]
+pub struct PairHaltUpdated {
+    pub pair_index: u8,
+    pub halted: bool,
+}
+
+#[event]
+pub struct OracleLimitsUpdated {
+    pub max_price_age: u64,
+    pub max_confidence_bps: u64,
+}
+
 #[event]
 pub struct AccountInitialized {
     pub user: Pubkey,
     pub pair_index: u8,
     pub initial_token_in: u64,
+    pub entry_fee: u64,          // Lamports paid to the treasury, 0 when waived
     pub timestamp: i64,
 }
 
+#[event]
+pub struct UserAccountMigrated {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub version: u8,
+}
+
+#[event]
+pub struct PositionMigrated {
+    pub user: Pubkey,
+    pub position_id: u64,
+    pub version: u8,
+}
+
+#[event]
+pub struct ConfigMigrated {
+    pub version: u8,
+}
+
 #[event]
 pub struct TradeExecuted {
     pub user: Pubkey,
@@ -765,6 +3195,10 @@ pub struct TradeExecuted {
     pub trade_type: TradeType,
     pub amount: u64,
     pub price: u64,
+    pub token_in_amount: u64,    // token_in paid (buy) or received (sell)
+    pub token_in_balance: u64,   // Balances after the trade
+    pub token_out_balance: u64,
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
     pub timestamp: i64,
 }
 
@@ -778,6 +3212,11 @@ pub struct PositionOpened {
     pub entry_price: u64,
     pub tp_price: u64,
     pub sl_price: u64,
+    pub leverage: u8,
+    pub collateral: u64,
+    pub liquidation_price: u64,
+    pub token_in_balance: u64,   // Balance after the collateral is locked
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
     pub timestamp: i64,
 }
 
@@ -788,6 +3227,109 @@ pub struct PositionClosed {
     pub position_id: u64,
     pub close_price: u64,
     pub close_reason: CloseReason,
+    pub position_type: PositionType,
+    pub amount: u64,
+    pub entry_price: u64,
+    pub realized_pnl: i64,       // token_in, after the bankruptcy cap and the liquidation fee
+    pub token_in_balance: u64,   // Balance after the close
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionReduced {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub position_id: u64,
+    pub closed_amount: u64,
+    pub remaining_amount: u64,
+    pub close_price: u64,
+    pub realized_pnl: i64,       // token_in, after the bankruptcy cap
+    pub token_in_balance: u64,   // Balance after the close
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionUpdated {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub position_id: u64,
+    pub tp_price: u64,
+    pub sl_price: u64,
+    pub current_price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct TrailingStopUpdated {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub position_id: u64,
+    pub mode: TrailingStopMode,  // None when the trailing stop is removed
+    pub high_water_mark: u64,
+    pub sl_price: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionAccountClosed {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub position_id: u64,
+}
+
+#[event]
+pub struct PositionLiquidated {
+    pub user: Pubkey,
+    pub liquidator: Pubkey,
+    pub pair_index: u8,
+    pub position_id: u64,
+    pub liquidation_price: u64,
+    pub close_price: u64,
+    pub liquidation_fee: u64,
+    pub liquidator_token_in_balance: u64, // Balance of the liquidator after the fee
+    pub liquidator_sequence: u64,         // Per liquidator account, see UserAccount::event_sequence
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderPlaced {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub order_id: u64,
+    pub side: PositionType,
+    pub amount: u64,
+    pub limit_price: u64,
+    pub leverage: u8,
+    pub reserved_collateral: u64,
+    pub expires_at: i64,
+    pub token_in_balance: u64,   // Balance after the reservation
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub order_id: u64,
+    pub released_collateral: u64,
+    pub token_in_balance: u64,   // Balance after the release
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OrderFilled {
+    pub user: Pubkey,
+    pub pair_index: u8,
+    pub order_id: u64,
+    pub position_id: u64,
+    pub fill_price: u64,
+    pub oracle_price: u64,
+    pub token_in_balance: u64,   // Balance after the fill
+    pub sequence: u64,           // Per account, see UserAccount::event_sequence
     pub timestamp: i64,
 }
 
@@ -795,8 +3337,8 @@ pub struct PositionClosed {
 
 #[error_code]
 pub enum ErrorCode {
-    #[msg("Entry fee is too low (minimum 0.1 SOL)")]
-    EntryFeeTooLow,
+    #[msg("Entry fee is above the maximum accepted by the user")]
+    EntryFeeAboveMax,
 
     #[msg("Insufficient mock balance")]
     InsufficientBalance,
@@ -824,4 +3366,97 @@ pub enum ErrorCode {
 
     #[msg("Pair index mismatch between position and user account")]
     PairMismatch,
+
+    #[msg("Invalid oracle limits")]
+    InvalidOracleLimits,
+
+    #[msg("Price update feed id does not match the pair")]
+    PriceFeedMismatch,
+
+    #[msg("Price update is not fully verified")]
+    PriceNotVerified,
+
+    #[msg("Price update is too old")]
+    StalePrice,
+
+    #[msg("Price confidence interval is too wide")]
+    PriceConfidenceTooWide,
+
+    #[msg("Invalid oracle price")]
+    InvalidOraclePrice,
+
+    #[msg("Invalid pair parameters")]
+    InvalidPairParams,
+
+    #[msg("Trading is disabled on this pair")]
+    PairDisabled,
+
+    #[msg("Order size is out of the pair limits")]
+    OrderSizeOutOfRange,
+
+    #[msg("Token decimals do not match the pair config")]
+    DecimalsMismatch,
+
+    #[msg("Leverage is above the max leverage of the pair")]
+    InvalidLeverage,
+
+    #[msg("Position has not reached its liquidation price")]
+    PositionNotLiquidatable,
+
+    #[msg("The owner of a position can't liquidate it")]
+    SelfLiquidation,
+
+    #[msg("Account uses an older layout, it must be migrated first")]
+    AccountNotMigrated,
+
+    #[msg("Account is already at the current version")]
+    AccountAlreadyMigrated,
+
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+
+    #[msg("Insufficient balance to lock the collateral of the position")]
+    InsufficientCollateral,
+
+    #[msg("Invalid limit price")]
+    InvalidLimitPrice,
+
+    #[msg("Order expiry must be in the future")]
+    InvalidOrderExpiry,
+
+    #[msg("Order is not open")]
+    OrderNotOpen,
+
+    #[msg("Order has expired")]
+    OrderExpired,
+
+    #[msg("Oracle price has not crossed the limit price")]
+    LimitPriceNotReached,
+
+    #[msg("Close amount must be positive and at most the size of the position")]
+    InvalidCloseAmount,
+
+    #[msg("Invalid trailing stop")]
+    InvalidTrailingStop,
+
+    #[msg("Position is still active")]
+    PositionNotClosed,
+
+    #[msg("Executor whitelist is full")]
+    TooManyExecutors,
+
+    #[msg("Trading is paused")]
+    TradingPaused,
+
+    #[msg("Trading is halted on this pair")]
+    PairHalted,
+
+    #[msg("Price update is older than a price already used")]
+    OutdatedPrice,
+
+    #[msg("Remaining size of the position would be below the min order size")]
+    RemainingSizeTooSmall,
+
+    #[msg("Price update was published after the time it prices")]
+    PricePublishedTooLate,
 }
\ No newline at end of file
@@ -0,0 +1,193 @@
+//! Pyth pull-oracle price reading, shared by the instructions of this program
+//! and by the ECS systems (programs-ecs) so both check prices the same way
+
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+
+use crate::{ErrorCode, PairConfig, ProgramConfig, UserAccount, PRICE_DECIMALS};
+
+/// Pyth pull-oracle receiver program (owner of the PriceUpdateV2 accounts)
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+/// Mirror of the Pyth receiver `PriceUpdateV2` account (pull oracle)
+/// Only the accounts owned by the Pyth receiver program are accepted
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PriceUpdateV2 {
+    pub write_authority: Pubkey,
+    pub verification_level: VerificationLevel,
+    pub price_message: PriceFeedMessage,
+    pub posted_slot: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct PriceFeedMessage {
+    pub feed_id: [u8; 32],
+    pub price: i64,
+    pub conf: u64,
+    pub exponent: i32,
+    pub publish_time: i64,
+    pub prev_publish_time: i64,
+    pub ema_price: i64,
+    pub ema_conf: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum VerificationLevel {
+    Partial { num_signatures: u8 },
+    Full,
+}
+
+impl Owner for PriceUpdateV2 {
+    fn owner() -> Pubkey {
+        PYTH_RECEIVER_PROGRAM_ID
+    }
+}
+
+impl Discriminator for PriceUpdateV2 {
+    // sha256("account:PriceUpdateV2")[..8]
+    const DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+}
+
+impl AccountDeserialize for PriceUpdateV2 {
+    fn try_deserialize(buf: &mut &[u8]) -> Result<Self> {
+        if buf.len() < Self::DISCRIMINATOR.len() {
+            return Err(anchor_lang::error::ErrorCode::AccountDiscriminatorNotFound.into());
+        }
+        if buf[..8] != Self::DISCRIMINATOR {
+            return Err(anchor_lang::error::ErrorCode::AccountDiscriminatorMismatch.into());
+        }
+        Self::try_deserialize_unchecked(buf)
+    }
+
+    fn try_deserialize_unchecked(buf: &mut &[u8]) -> Result<Self> {
+        let mut data: &[u8] = &buf[8..];
+        AnchorDeserialize::deserialize(&mut data)
+            .map_err(|_| anchor_lang::error::ErrorCode::AccountDidNotDeserialize.into())
+    }
+}
+
+// Read-only for this program, never written back
+impl AccountSerialize for PriceUpdateV2 {}
+
+/// Read the price of a pair from a Pyth price update
+/// Checks the feed id against the pair config, the staleness and the confidence,
+/// then converts the price to PRICE_DECIMALS
+pub fn get_oracle_price(
+    price_update: &PriceUpdateV2,
+    config: &ProgramConfig,
+    pair_config: &PairConfig,
+    clock: &Clock,
+) -> Result<u64> {
+    oracle_price_at(price_update, config, pair_config, clock.unix_timestamp)
+}
+
+/// get_oracle_price with the staleness measured at `at` instead of the current time
+fn oracle_price_at(
+    price_update: &PriceUpdateV2,
+    config: &ProgramConfig,
+    pair_config: &PairConfig,
+    at: i64,
+) -> Result<u64> {
+    require!(
+        price_update.verification_level == VerificationLevel::Full,
+        ErrorCode::PriceNotVerified
+    );
+
+    let message = &price_update.price_message;
+
+    require!(
+        message.feed_id == pair_config.feed_id,
+        ErrorCode::PriceFeedMismatch
+    );
+    require!(
+        message
+            .publish_time
+            .saturating_add(config.max_price_age as i64)
+            >= at,
+        ErrorCode::StalePrice
+    );
+    require!(message.price > 0, ErrorCode::InvalidOraclePrice);
+
+    // conf / price <= max_confidence_bps / 10_000 (both share the same exponent)
+    require!(
+        (message.conf as u128) * 10_000
+            <= (message.price as u128) * (config.max_confidence_bps as u128),
+        ErrorCode::PriceConfidenceTooWide
+    );
+
+    // Rescale from the feed exponent to PRICE_DECIMALS
+    let scale = PRICE_DECIMALS + message.exponent;
+    let factor = 10u128
+        .checked_pow(scale.unsigned_abs())
+        .ok_or(ErrorCode::InvalidOraclePrice)?;
+    let price = if scale >= 0 {
+        (message.price as u128)
+            .checked_mul(factor)
+            .ok_or(ErrorCode::InvalidOraclePrice)?
+    } else {
+        (message.price as u128) / factor
+    };
+
+    require!(price > 0, ErrorCode::InvalidOraclePrice);
+
+    u64::try_from(price).map_err(|_| ErrorCode::InvalidOraclePrice.into())
+}
+
+/// Read the price of a trade on a user account (see get_oracle_price)
+/// The update can't be older than the last one used by the account, nor than
+/// `not_before` (ex: the opening of the position being closed): otherwise any update
+/// of the last max_price_age could be picked, whichever suits the trade
+pub fn get_trade_price(
+    price_update: &PriceUpdateV2,
+    config: &ProgramConfig,
+    pair_config: &PairConfig,
+    user_account: &mut UserAccount,
+    not_before: i64,
+    clock: &Clock,
+) -> Result<u64> {
+    let publish_time = price_update.price_message.publish_time;
+    require!(
+        publish_time >= not_before.max(user_account.last_price_time),
+        ErrorCode::OutdatedPrice
+    );
+
+    let price = get_oracle_price(price_update, config, pair_config, clock)?;
+    user_account.last_price_time = publish_time;
+
+    Ok(price)
+}
+
+/// Read the price of a pair from accounts passed unchecked (ECS systems)
+/// The price update must be owned by the Pyth receiver, the pair config and the
+/// program config by this program: the feed id and the oracle limits come from them
+/// It prices the pair at `at`, the current time or the end of a competition being
+/// settled: the update must be published between `not_before` (ex: the opening of the
+/// position) and `at`, and be fresh at `at`
+pub fn get_pair_price(
+    price_update: &AccountInfo,
+    pair_config: &AccountInfo,
+    config: &AccountInfo,
+    pair_index: u8,
+    not_before: i64,
+    at: i64,
+) -> Result<u64> {
+    let price_update: PriceUpdateV2 = load(price_update, &PriceUpdateV2::owner())?;
+    let pair_config: PairConfig = load(pair_config, &crate::ID)?;
+    let config: ProgramConfig = load(config, &crate::ID)?;
+
+    require!(pair_config.pair_index == pair_index, ErrorCode::PairMismatch);
+    let publish_time = price_update.price_message.publish_time;
+    require!(publish_time >= not_before, ErrorCode::OutdatedPrice);
+    require!(publish_time <= at, ErrorCode::PricePublishedTooLate);
+
+    oracle_price_at(&price_update, &config, &pair_config, at)
+}
+
+/// Deserialize an account after checking its owner (the discriminator is checked by try_deserialize)
+fn load<T: AccountDeserialize>(account: &AccountInfo, owner: &Pubkey) -> Result<T> {
+    if account.owner != owner {
+        return Err(anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram.into());
+    }
+    let data = account.try_borrow_data()?;
+    T::try_deserialize(&mut &data[..])
+}
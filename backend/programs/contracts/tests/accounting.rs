@@ -0,0 +1,272 @@
+use anchor_lang::prelude::*;
+use bytemuck::Zeroable;
+use paper_trading::{
+    close_position_logic, open_position_logic, reduce_position_logic, CloseReason, ErrorCode,
+    PairConfig, PositionAccount, PositionStatus, PositionType, TradeHistory, UserAccount,
+    USER_ACCOUNT_VERSION,
+};
+use proptest::prelude::*;
+
+const MAX_LEVERAGE: u8 = 20;
+
+fn pair_config(token_in_decimals: u8, token_out_decimals: u8) -> PairConfig {
+    PairConfig {
+        pair_index: 0,
+        symbol: "SOL/USDT".to_string(),
+        token_in_decimals,
+        token_out_decimals,
+        feed_id: [0xef; 32],
+        min_order_size: 1,
+        max_order_size: u64::MAX,
+        enabled: true,
+        bump: 255,
+        max_leverage: MAX_LEVERAGE,
+        maintenance_margin_bps: 50,
+        liquidation_fee_bps: 100,
+    }
+}
+
+fn user_account(pair_config: &PairConfig, token_in_balance: u64) -> UserAccount {
+    UserAccount {
+        owner: Pubkey::new_unique(),
+        pair_index: pair_config.pair_index,
+        token_in_balance,
+        token_out_balance: 0,
+        token_in_decimals: pair_config.token_in_decimals,
+        token_out_decimals: pair_config.token_out_decimals,
+        total_positions: 0,
+        created_at: 0,
+        version: USER_ACCOUNT_VERSION,
+        ..Default::default()
+    }
+}
+
+fn position_type(long: bool) -> PositionType {
+    if long {
+        PositionType::Long
+    } else {
+        PositionType::Short
+    }
+}
+
+proptest! {
+    /// Opening then closing at the entry price gives back the exact balance, in both directions
+    #[test]
+    fn round_trip_at_entry_price_restores_balance(
+        long in any::<bool>(),
+        balance in 0u64..=1_000_000_000_000_000,
+        amount in 1u64..=1_000_000_000_000,
+        entry_price in 1u64..=1_000_000_000_000,
+        leverage in 1u8..=MAX_LEVERAGE,
+        token_in_decimals in 0u8..=9,
+        token_out_decimals in 0u8..=9,
+    ) {
+        let pair_config = pair_config(token_in_decimals, token_out_decimals);
+        let mut user = user_account(&pair_config, balance);
+        let mut position = PositionAccount::default();
+        let mut history = TradeHistory::zeroed();
+
+        let opened = open_position_logic(
+            &mut position,
+            &mut user,
+            &pair_config,
+            position_type(long),
+            amount,
+            leverage,
+            entry_price,
+            0,
+            0,
+            1,
+        );
+        if opened.is_err() {
+            // A rejected order leaves the account untouched
+            prop_assert_eq!(user.token_in_balance, balance);
+            prop_assert_eq!(user.total_positions, 0);
+            return Ok(());
+        }
+        prop_assert_eq!(user.token_in_balance, balance - position.collateral);
+
+        let (payout, _) = close_position_logic(
+            &mut position,
+            &mut user,
+            &mut history,
+            entry_price,
+            CloseReason::Manual,
+            0,
+            2,
+        )
+        .unwrap();
+
+        prop_assert_eq!(payout, position.collateral);
+        prop_assert_eq!(user.token_in_balance, balance);
+        prop_assert_eq!(user.token_out_balance, 0);
+        prop_assert!(position.status == PositionStatus::Closed);
+        prop_assert_eq!(position.closed_at, 2);
+    }
+
+    /// A long and a short of the same size closed at the same price have opposite PnL
+    /// as long as neither is capped at its collateral
+    #[test]
+    fn long_and_short_pnl_are_symmetric(
+        amount in 1u64..=1_000_000_000_000,
+        entry_price in 1u64..=1_000_000_000_000,
+        close_bps in 0u64..=20_000,
+    ) {
+        let pair_config = pair_config(6, 9);
+        let balance = u64::MAX / 4;
+        let close_price = entry_price * close_bps / 10_000;
+
+        // (pnl, collateral, whole collateral lost) of the long then the short
+        let mut closes = Vec::new();
+        for long in [true, false] {
+            let mut user = user_account(&pair_config, balance);
+            let mut position = PositionAccount::default();
+            let mut history = TradeHistory::zeroed();
+
+            let opened = open_position_logic(
+                &mut position,
+                &mut user,
+                &pair_config,
+                position_type(long),
+                amount,
+                1,
+                entry_price,
+                0,
+                0,
+                1,
+            );
+            prop_assume!(opened.is_ok());
+            let collateral = position.collateral as i128;
+            let (payout, _) =
+                close_position_logic(&mut position, &mut user, &mut history, close_price, CloseReason::Manual, 0, 2)
+                    .unwrap();
+
+            closes.push((user.token_in_balance as i128 - balance as i128, collateral, payout == 0));
+        }
+
+        // A side that lost its whole collateral is capped, the other side gains at least as much
+        match (closes[0], closes[1]) {
+            ((lost_pnl, collateral, true), (won_pnl, _, false))
+            | ((won_pnl, _, false), (lost_pnl, collateral, true)) => {
+                prop_assert_eq!(lost_pnl, -collateral);
+                prop_assert!(won_pnl >= collateral);
+            }
+            ((long_pnl, _, false), (short_pnl, _, false)) => prop_assert_eq!(long_pnl, -short_pnl),
+            _ => prop_assert!(false, "both sides lost their whole collateral"),
+        }
+    }
+
+    /// Closing a position in two steps at the entry price gives back the exact balance
+    #[test]
+    fn partial_close_at_entry_price_restores_balance(
+        long in any::<bool>(),
+        amount in 2u64..=1_000_000_000_000,
+        entry_price in 1u64..=1_000_000_000_000,
+        leverage in 1u8..=MAX_LEVERAGE,
+        closed_bps in 1u64..10_000,
+    ) {
+        let pair_config = pair_config(6, 9);
+        let balance = 1_000_000_000_000_000;
+        let mut user = user_account(&pair_config, balance);
+        let mut position = PositionAccount::default();
+        let mut history = TradeHistory::zeroed();
+
+        let opened = open_position_logic(
+            &mut position,
+            &mut user,
+            &pair_config,
+            position_type(long),
+            amount,
+            leverage,
+            entry_price,
+            0,
+            0,
+            1,
+        );
+        prop_assume!(opened.is_ok());
+
+        let closed = (amount * closed_bps / 10_000).max(1);
+        reduce_position_logic(&mut position, &mut user, &pair_config, &mut history, closed, entry_price, 2)
+            .unwrap();
+        prop_assert!(position.status == PositionStatus::Active);
+        prop_assert_eq!(position.amount_token_out, amount - closed);
+
+        close_position_logic(&mut position, &mut user, &mut history, entry_price, CloseReason::Manual, 0, 3)
+            .unwrap();
+        prop_assert_eq!(user.token_in_balance, balance);
+    }
+
+    /// A close always succeeds and the loss never exceeds the collateral, even when it
+    /// is larger than what is left on the account
+    #[test]
+    fn close_is_bounded_by_collateral(
+        long in any::<bool>(),
+        amount in 1u64..=1_000_000_000_000,
+        entry_price in 1u64..=1_000_000_000_000,
+        close_price in 0u64..=u64::MAX / 1_000_000,
+        leverage in 1u8..=MAX_LEVERAGE,
+    ) {
+        let pair_config = pair_config(6, 9);
+        let mut user = user_account(&pair_config, u64::MAX);
+        let mut position = PositionAccount::default();
+        let mut history = TradeHistory::zeroed();
+
+        let opened = open_position_logic(
+            &mut position,
+            &mut user,
+            &pair_config,
+            position_type(long),
+            amount,
+            leverage,
+            entry_price,
+            0,
+            0,
+            1,
+        );
+        prop_assume!(opened.is_ok());
+        // Nothing left outside of the collateral to absorb a loss
+        user.token_in_balance = 0;
+
+        let (payout, _) = close_position_logic(
+            &mut position,
+            &mut user,
+            &mut history,
+            close_price,
+            CloseReason::Manual,
+            0,
+            2,
+        )
+        .unwrap();
+
+        prop_assert_eq!(user.token_in_balance, payout);
+        prop_assert!(position.status == PositionStatus::Closed);
+        if long && close_price <= entry_price || !long && close_price >= entry_price {
+            prop_assert!(payout <= position.collateral);
+        }
+    }
+}
+
+#[test]
+fn open_overflow_is_an_error() {
+    let pair_config = pair_config(18, 0);
+    let mut user = user_account(&pair_config, u64::MAX);
+    let mut position = PositionAccount::default();
+
+    let err = open_position_logic(
+        &mut position,
+        &mut user,
+        &pair_config,
+        PositionType::Short,
+        u64::MAX,
+        1,
+        u64::MAX,
+        0,
+        0,
+        1,
+    )
+    .unwrap_err();
+
+    assert_eq!(err, ErrorCode::MathOverflow.into());
+    assert_eq!(user.token_in_balance, u64::MAX);
+    assert_eq!(user.total_positions, 0);
+}
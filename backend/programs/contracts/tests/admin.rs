@@ -0,0 +1,237 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{AdminRole, ErrorCode, ProgramConfig};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+};
+
+async fn start() -> ProgramTestContext {
+    let mut ctx = program_test().start_with_context().await;
+    setup_config(&mut ctx, Pubkey::new_unique()).await;
+    ctx
+}
+
+fn propose_authority_ix(authority: Pubkey, new_authority: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::ProposeAuthority {
+            config: config_pda(),
+            authority,
+        },
+        paper_trading::instruction::ProposeAuthority { new_authority },
+    )
+}
+
+fn accept_authority_ix(new_authority: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::AcceptAuthority {
+            config: config_pda(),
+            new_authority,
+        },
+        paper_trading::instruction::AcceptAuthority {},
+    )
+}
+
+fn set_role_ix(authority: Pubkey, role: AdminRole, key: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::SetRole {
+            config: config_pda(),
+            authority,
+        },
+        paper_trading::instruction::SetRole { role, key },
+    )
+}
+
+fn update_treasury_ix(fee_manager: Pubkey, treasury: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTreasury {
+            config: config_pda(),
+            fee_manager,
+        },
+        paper_trading::instruction::UpdateTreasury { treasury },
+    )
+}
+
+fn set_pause_ix(pauser: Pubkey, paused: bool) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTradingStatus {
+            config: config_pda(),
+            pauser,
+        },
+        paper_trading::instruction::SetPause { paused },
+    )
+}
+
+fn add_executor_ix(executor_manager: Pubkey, executor: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateExecutors {
+            config: config_pda(),
+            executor_manager,
+        },
+        paper_trading::instruction::AddExecutor { executor },
+    )
+}
+
+#[tokio::test]
+async fn authority_transfer_needs_acceptance() {
+    let mut ctx = start().await;
+    let authority = ctx.payer.pubkey();
+    let new_authority = Keypair::new();
+    let intruder = Keypair::new();
+
+    let result = send(
+        &mut ctx,
+        &[propose_authority_ix(intruder.pubkey(), intruder.pubkey())],
+        &[&intruder],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[propose_authority_ix(authority, new_authority.pubkey())],
+        &[],
+    )
+    .await
+    .unwrap();
+    let config: ProgramConfig = fetch(&mut ctx, config_pda()).await;
+    assert_eq!(config.authority, authority);
+    assert_eq!(config.pending_authority, new_authority.pubkey());
+
+    // Only the proposed key accepts
+    let result = send(
+        &mut ctx,
+        &[accept_authority_ix(intruder.pubkey())],
+        &[&intruder],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[accept_authority_ix(new_authority.pubkey())],
+        &[&new_authority],
+    )
+    .await
+    .unwrap();
+    let config: ProgramConfig = fetch(&mut ctx, config_pda()).await;
+    assert_eq!(config.authority, new_authority.pubkey());
+    assert_eq!(config.pending_authority, Pubkey::default());
+
+    // The previous authority lost every right
+    let result = send(&mut ctx, &[propose_authority_ix(authority, authority)], &[]).await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+    let result = send(
+        &mut ctx,
+        &[update_treasury_ix(authority, Pubkey::new_unique())],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+}
+
+#[tokio::test]
+async fn roles_are_limited_to_their_instructions() {
+    let mut ctx = start().await;
+    let authority = ctx.payer.pubkey();
+    let pauser = Keypair::new();
+    let executor_manager = Keypair::new();
+    let fee_manager = Keypair::new();
+
+    let result = send(
+        &mut ctx,
+        &[set_role_ix(
+            pauser.pubkey(),
+            AdminRole::Pauser,
+            pauser.pubkey(),
+        )],
+        &[&pauser],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[
+            set_role_ix(authority, AdminRole::Pauser, pauser.pubkey()),
+            set_role_ix(
+                authority,
+                AdminRole::ExecutorManager,
+                executor_manager.pubkey(),
+            ),
+            set_role_ix(authority, AdminRole::FeeManager, fee_manager.pubkey()),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    send(&mut ctx, &[set_pause_ix(pauser.pubkey(), true)], &[&pauser])
+        .await
+        .unwrap();
+    let executor = Pubkey::new_unique();
+    send(
+        &mut ctx,
+        &[add_executor_ix(executor_manager.pubkey(), executor)],
+        &[&executor_manager],
+    )
+    .await
+    .unwrap();
+    let treasury = Pubkey::new_unique();
+    send(
+        &mut ctx,
+        &[update_treasury_ix(fee_manager.pubkey(), treasury)],
+        &[&fee_manager],
+    )
+    .await
+    .unwrap();
+
+    let config: ProgramConfig = fetch(&mut ctx, config_pda()).await;
+    assert!(config.paused);
+    assert_eq!(config.authorized_executors, vec![executor]);
+    assert_eq!(config.treasury, treasury);
+
+    let result = send(
+        &mut ctx,
+        &[set_pause_ix(executor_manager.pubkey(), false)],
+        &[&executor_manager],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+    let result = send(
+        &mut ctx,
+        &[add_executor_ix(fee_manager.pubkey(), Pubkey::new_unique())],
+        &[&fee_manager],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+    let result = send(
+        &mut ctx,
+        &[update_treasury_ix(pauser.pubkey(), Pubkey::new_unique())],
+        &[&pauser],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    // A removed role can't be used anymore, the authority still holds it
+    send(
+        &mut ctx,
+        &[set_role_ix(authority, AdminRole::Pauser, Pubkey::default())],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(
+        &mut ctx,
+        &[set_pause_ix(pauser.pubkey(), false)],
+        &[&pauser],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+    send(&mut ctx, &[set_pause_ix(authority, false)], &[])
+        .await
+        .unwrap();
+}
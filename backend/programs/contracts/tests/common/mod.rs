@@ -0,0 +1,447 @@
+#![allow(dead_code)]
+
+use anchor_lang::prelude::*;
+use anchor_lang::{Discriminator, InstructionData};
+use paper_trading::{
+    ErrorCode, PairParams, PriceFeedMessage, PriceUpdateV2, VerificationLevel,
+    PYTH_RECEIVER_PROGRAM_ID,
+};
+use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    instruction::{Instruction, InstructionError},
+    signature::{Keypair, Signer},
+    system_program,
+    transaction::{Transaction, TransactionError},
+};
+
+pub const NOW: i64 = 1_700_000_000;
+
+pub const SOL_PAIR: u8 = 0;
+pub const SOL_FEED: [u8; 32] = [0xef; 32];
+pub const BTC_FEED: [u8; 32] = [0xe6; 32];
+
+pub const ENTRY_FEE: u64 = 100_000_000;
+pub const INITIAL_TOKEN_IN: u64 = 10_000 * 1_000_000;
+
+// Anchor ties the accounts slice to the AccountInfo lifetime, the test processor can't express it
+fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    paper_trading::entry(program_id, accounts, data)
+}
+
+pub fn program_test() -> ProgramTest {
+    ProgramTest::new(
+        "paper_trading",
+        paper_trading::ID,
+        processor!(process_instruction),
+    )
+}
+
+/// Build a PriceUpdateV2 account as posted by the Pyth receiver
+pub fn price_update_account(
+    feed_id: [u8; 32],
+    price: i64,
+    conf: u64,
+    exponent: i32,
+    publish_time: i64,
+) -> Account {
+    let update = PriceUpdateV2 {
+        write_authority: Pubkey::new_unique(),
+        verification_level: VerificationLevel::Full,
+        price_message: PriceFeedMessage {
+            feed_id,
+            price,
+            conf,
+            exponent,
+            publish_time,
+            prev_publish_time: publish_time - 1,
+            ema_price: price,
+            ema_conf: conf,
+        },
+        posted_slot: 1,
+    };
+
+    let mut data = PriceUpdateV2::DISCRIMINATOR.to_vec();
+    update.serialize(&mut data).unwrap();
+
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: PYTH_RECEIVER_PROGRAM_ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// SOL/USDT at 150$ with the Pyth exponent -8
+pub fn sol_price_account(publish_time: i64) -> Account {
+    sol_price_account_at(15_000_000_000, publish_time)
+}
+
+/// SOL/USDT at a price with the Pyth exponent -8 (ex: 13_570_000_000 = 135.70$)
+pub fn sol_price_account_at(price: i64, publish_time: i64) -> Account {
+    price_update_account(SOL_FEED, price, 1_000_000, -8, publish_time)
+}
+
+pub fn config_pda() -> Pubkey {
+    Pubkey::find_program_address(&[b"config"], &paper_trading::ID).0
+}
+
+pub fn pair_config_pda(pair_index: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"pair", &[pair_index]], &paper_trading::ID).0
+}
+
+pub fn user_account_pda(user: &Pubkey, pair_index: u8) -> Pubkey {
+    Pubkey::find_program_address(&[b"user", user.as_ref(), &[pair_index]], &paper_trading::ID).0
+}
+
+pub fn position_pda(user: &Pubkey, pair_index: u8, position_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"position",
+            user.as_ref(),
+            &[pair_index],
+            position_id.to_le_bytes().as_ref(),
+        ],
+        &paper_trading::ID,
+    )
+    .0
+}
+
+pub fn order_pda(user: &Pubkey, pair_index: u8, order_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            b"order",
+            user.as_ref(),
+            &[pair_index],
+            order_id.to_le_bytes().as_ref(),
+        ],
+        &paper_trading::ID,
+    )
+    .0
+}
+
+pub fn fee_waiver_pda(user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"fee_waiver", user.as_ref()], &paper_trading::ID).0
+}
+
+pub fn trade_history_pda(user: &Pubkey, pair_index: u8) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"history", user.as_ref(), &[pair_index]],
+        &paper_trading::ID,
+    )
+    .0
+}
+
+pub fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: paper_trading::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+pub async fn set_clock(ctx: &mut ProgramTestContext, unix_timestamp: i64) {
+    let mut clock: Clock = ctx.banks_client.get_sysvar().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    ctx.set_sysvar(&clock);
+}
+
+pub async fn send(
+    ctx: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> std::result::Result<(), BanksClientError> {
+    // A fresh blockhash so that identical instructions are not deduplicated
+    let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&ctx.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        instructions,
+        Some(&ctx.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    ctx.banks_client.process_transaction(tx).await
+}
+
+pub fn assert_error(result: std::result::Result<(), BanksClientError>, expected: u32) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+            assert_eq!(code, expected)
+        }
+        err => panic!("unexpected error: {err:?}"),
+    }
+}
+
+pub fn assert_program_error(
+    result: std::result::Result<(), BanksClientError>,
+    expected: ErrorCode,
+) {
+    assert_error(result, expected.into())
+}
+
+pub async fn fetch<T: AccountDeserialize>(ctx: &mut ProgramTestContext, address: Pubkey) -> T {
+    let account = ctx
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    T::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+pub fn sol_pair_params() -> PairParams {
+    PairParams {
+        symbol: "SOL/USDT".to_string(),
+        token_in_decimals: 6,
+        token_out_decimals: 9,
+        feed_id: SOL_FEED,
+        min_order_size: 1_000,
+        max_order_size: 1_000_000_000_000,
+        enabled: true,
+        max_leverage: 20,
+        maintenance_margin_bps: 50,
+        liquidation_fee_bps: 100,
+    }
+}
+
+/// Create the config (payer is the authority) and the SOL/USDT pair
+pub async fn setup_config(ctx: &mut ProgramTestContext, treasury: Pubkey) {
+    let authority = ctx.payer.pubkey();
+    send(
+        ctx,
+        &[
+            instruction(
+                paper_trading::accounts::InitializeConfig {
+                    config: config_pda(),
+                    authority,
+                    system_program: system_program::ID,
+                },
+                paper_trading::instruction::InitializeConfig { treasury },
+            ),
+            instruction(
+                paper_trading::accounts::InitializePair {
+                    config: config_pda(),
+                    pair_config: pair_config_pda(SOL_PAIR),
+                    authority,
+                    system_program: system_program::ID,
+                },
+                paper_trading::instruction::InitializePair {
+                    pair_index: SOL_PAIR,
+                    params: sol_pair_params(),
+                },
+            ),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+/// SOL at 150$, the payer creates the config and its SOL/USDT account, then opens
+/// `position` as position 0. Returns the context, the price update and the treasury
+pub async fn start_with_position(
+    position: impl InstructionData,
+) -> (ProgramTestContext, Pubkey, Pubkey) {
+    let mut pt = program_test();
+    let price_update = Pubkey::new_unique();
+    pt.add_account(price_update, sol_price_account(NOW));
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+
+    let treasury = Pubkey::new_unique();
+    setup_config(&mut ctx, treasury).await;
+    setup_user_account(&mut ctx, treasury).await;
+
+    let user = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[open_position_ix(user, price_update, 0, position)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    (ctx, price_update, treasury)
+}
+
+/// 10 SOL LONG with a TP at 200$ and a SL at 100$
+pub fn long_10_sol(leverage: u8) -> paper_trading::instruction::OpenLongPosition {
+    paper_trading::instruction::OpenLongPosition {
+        amount_token_out: 10_000_000_000,
+        leverage,
+        take_profit_price: 200_000_000,
+        stop_loss_price: 100_000_000,
+    }
+}
+
+/// Create the SOL/USDT user account of the payer
+pub async fn setup_user_account(ctx: &mut ProgramTestContext, treasury: Pubkey) {
+    let user = ctx.payer.pubkey();
+    send(ctx, &[initialize_account_ix(user, treasury)], &[])
+        .await
+        .unwrap();
+}
+
+/// Fund a new wallet and create its SOL/USDT user account
+pub async fn setup_other_user(ctx: &mut ProgramTestContext, treasury: Pubkey) -> Keypair {
+    let user = Keypair::new();
+    let fund_ix = solana_sdk::system_instruction::transfer(
+        &ctx.payer.pubkey(),
+        &user.pubkey(),
+        1_000_000_000,
+    );
+    send(
+        ctx,
+        &[fund_ix, initialize_account_ix(user.pubkey(), treasury)],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    user
+}
+
+/// Fund a new wallet and add it to the executor whitelist (payer is the authority)
+pub async fn setup_executor(ctx: &mut ProgramTestContext) -> Keypair {
+    let executor = Keypair::new();
+    let authority = ctx.payer.pubkey();
+    let fund_ix =
+        solana_sdk::system_instruction::transfer(&authority, &executor.pubkey(), 1_000_000_000);
+    let add_executor_ix = instruction(
+        paper_trading::accounts::UpdateExecutors {
+            config: config_pda(),
+            executor_manager: authority,
+        },
+        paper_trading::instruction::AddExecutor {
+            executor: executor.pubkey(),
+        },
+    );
+    send(ctx, &[fund_ix, add_executor_ix], &[]).await.unwrap();
+    executor
+}
+
+pub fn resize_config_ix(authority: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::ResizeConfig {
+            config: config_pda(),
+            authority,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::ResizeConfig {},
+    )
+}
+
+pub fn initialize_account_ix(user: Pubkey, treasury: Pubkey) -> Instruction {
+    initialize_account_with_fee_ix(user, treasury, ENTRY_FEE, None)
+}
+
+pub fn initialize_account_with_fee_ix(
+    user: Pubkey,
+    treasury: Pubkey,
+    max_entry_fee: u64,
+    fee_waiver: Option<Pubkey>,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::InitializeAccount {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            user,
+            treasury,
+            fee_waiver,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::InitializeAccount {
+            pair_index: SOL_PAIR,
+            max_entry_fee,
+            initial_token_in: INITIAL_TOKEN_IN,
+            token_in_decimals: 6,
+            token_out_decimals: 9,
+        },
+    )
+}
+
+/// Only needed for accounts created before initialize_account made the history
+pub fn initialize_trade_history_ix(user: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::InitializeTradeHistory {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            user,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::InitializeTradeHistory {},
+    )
+}
+
+pub fn buy_ix(user: Pubkey, price_update: Pubkey, amount_token_out: u64) -> Instruction {
+    instruction(
+        paper_trading::accounts::Trade {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::Buy { amount_token_out },
+    )
+}
+
+pub fn open_position_ix(
+    user: Pubkey,
+    price_update: Pubkey,
+    position_id: u64,
+    data: impl InstructionData,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::OpenPosition {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            position_account: position_pda(&user, SOL_PAIR, position_id),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+            system_program: system_program::ID,
+        },
+        data,
+    )
+}
+
+pub fn close_position_ix(user: Pubkey, price_update: Pubkey, position_id: u64) -> Instruction {
+    instruction(
+        paper_trading::accounts::ClosePositionManual {
+            position_account: position_pda(&user, SOL_PAIR, position_id),
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::ClosePosition {},
+    )
+}
+
+pub fn close_position_account_ix(
+    user: Pubkey,
+    rent_payer: Pubkey,
+    position_id: u64,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::ClosePositionAccount {
+            position_account: position_pda(&user, SOL_PAIR, position_id),
+            rent_payer,
+            user,
+        },
+        paper_trading::instruction::ClosePositionAccount {},
+    )
+}
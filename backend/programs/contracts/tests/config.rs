@@ -0,0 +1,136 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, ProgramConfig, UserAccount, MAX_EXECUTORS};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{account::Account, instruction::Instruction, signature::Signer};
+
+fn add_executor_ix(authority: Pubkey, executor: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateExecutors {
+            config: config_pda(),
+            executor_manager: authority,
+        },
+        paper_trading::instruction::AddExecutor { executor },
+    )
+}
+
+async fn data_len(ctx: &mut ProgramTestContext, address: Pubkey) -> usize {
+    ctx.banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+        .len()
+}
+
+#[tokio::test]
+async fn accounts_are_sized_with_init_space() {
+    let mut pt = program_test();
+    let price_update = Pubkey::new_unique();
+    pt.add_account(price_update, sol_price_account(NOW));
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+
+    let treasury = Pubkey::new_unique();
+    setup_config(&mut ctx, treasury).await;
+    setup_user_account(&mut ctx, treasury).await;
+    let user = ctx.payer.pubkey();
+    send(
+        &mut ctx,
+        &[open_position_ix(
+            user,
+            price_update,
+            0,
+            paper_trading::instruction::OpenLongPosition {
+                amount_token_out: 1_000_000_000,
+                leverage: 1,
+                take_profit_price: 200_000_000,
+                stop_loss_price: 100_000_000,
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        data_len(&mut ctx, config_pda()).await,
+        8 + ProgramConfig::INIT_SPACE
+    );
+    assert_eq!(
+        data_len(&mut ctx, user_account_pda(&user, SOL_PAIR)).await,
+        8 + UserAccount::INIT_SPACE
+    );
+    assert_eq!(
+        data_len(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await,
+        8 + PositionAccount::INIT_SPACE
+    );
+}
+
+#[tokio::test]
+async fn executor_whitelist_is_bounded() {
+    let mut ctx = program_test().start_with_context().await;
+    setup_config(&mut ctx, Pubkey::new_unique()).await;
+    let authority = ctx.payer.pubkey();
+
+    let executors: Vec<Instruction> = (0..MAX_EXECUTORS)
+        .map(|_| add_executor_ix(authority, Pubkey::new_unique()))
+        .collect();
+    send(&mut ctx, &executors, &[]).await.unwrap();
+
+    let result = send(
+        &mut ctx,
+        &[add_executor_ix(authority, Pubkey::new_unique())],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::TooManyExecutors);
+}
+
+#[tokio::test]
+async fn short_config_is_resized() {
+    let mut ctx = program_test().start_with_context().await;
+    setup_config(&mut ctx, Pubkey::new_unique()).await;
+    let authority = ctx.payer.pubkey();
+
+    // Config allocated for 2 executors only
+    let account = ctx
+        .banks_client
+        .get_account(config_pda())
+        .await
+        .unwrap()
+        .unwrap();
+    let mut data = account.data;
+    data.truncate(8 + ProgramConfig::INIT_SPACE - 32 * (MAX_EXECUTORS - 2));
+    ctx.set_account(
+        &config_pda(),
+        &Account {
+            lamports: account.lamports,
+            data,
+            owner: paper_trading::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+        .into(),
+    );
+
+    send(&mut ctx, &[resize_config_ix(authority)], &[])
+        .await
+        .unwrap();
+    assert_eq!(
+        data_len(&mut ctx, config_pda()).await,
+        8 + ProgramConfig::INIT_SPACE
+    );
+
+    let executors: Vec<Instruction> = (0..MAX_EXECUTORS)
+        .map(|_| add_executor_ix(authority, Pubkey::new_unique()))
+        .collect();
+    send(&mut ctx, &executors, &[]).await.unwrap();
+
+    let config: ProgramConfig = fetch(&mut ctx, config_pda()).await;
+    assert_eq!(config.authorized_executors.len(), MAX_EXECUTORS);
+}
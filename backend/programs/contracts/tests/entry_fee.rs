@@ -0,0 +1,180 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, DEFAULT_ENTRY_FEE_LAMPORTS};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    system_program,
+};
+
+async fn start(treasury: Pubkey) -> ProgramTestContext {
+    let mut ctx = program_test().start_with_context().await;
+    setup_config(&mut ctx, treasury).await;
+    ctx
+}
+
+fn set_entry_fee_ix(fee_manager: Pubkey, entry_fee_lamports: u64) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateEntryFee {
+            config: config_pda(),
+            fee_manager,
+        },
+        paper_trading::instruction::SetEntryFee { entry_fee_lamports },
+    )
+}
+
+fn grant_fee_waiver_ix(fee_manager: Pubkey, user: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::GrantFeeWaiver {
+            config: config_pda(),
+            fee_waiver: fee_waiver_pda(&user),
+            fee_manager,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::GrantFeeWaiver { user },
+    )
+}
+
+fn revoke_fee_waiver_ix(fee_manager: Pubkey, user: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::RevokeFeeWaiver {
+            config: config_pda(),
+            fee_waiver: fee_waiver_pda(&user),
+            fee_manager,
+        },
+        paper_trading::instruction::RevokeFeeWaiver {},
+    )
+}
+
+/// New wallet with enough lamports for the rent of its accounts and the fee
+async fn funded_user(ctx: &mut ProgramTestContext) -> Keypair {
+    let user = Keypair::new();
+    let fund_ix = solana_sdk::system_instruction::transfer(
+        &ctx.payer.pubkey(),
+        &user.pubkey(),
+        1_000_000_000,
+    );
+    send(ctx, &[fund_ix], &[]).await.unwrap();
+    user
+}
+
+async fn balance(ctx: &mut ProgramTestContext, address: Pubkey) -> u64 {
+    ctx.banks_client.get_balance(address).await.unwrap()
+}
+
+#[tokio::test]
+async fn configured_fee_is_charged_exactly() {
+    let treasury = Pubkey::new_unique();
+    let mut ctx = start(treasury).await;
+    let authority = ctx.payer.pubkey();
+    let user = funded_user(&mut ctx).await;
+
+    let result = send(&mut ctx, &[set_entry_fee_ix(user.pubkey(), 0)], &[&user]).await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(&mut ctx, &[set_entry_fee_ix(authority, 200_000_000)], &[])
+        .await
+        .unwrap();
+
+    // The user only accepts the old price
+    let result = send(
+        &mut ctx,
+        &[initialize_account_with_fee_ix(
+            user.pubkey(),
+            treasury,
+            DEFAULT_ENTRY_FEE_LAMPORTS,
+            None,
+        )],
+        &[&user],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::EntryFeeAboveMax);
+
+    // A higher maximum doesn't make the user overpay
+    send(
+        &mut ctx,
+        &[initialize_account_with_fee_ix(
+            user.pubkey(),
+            treasury,
+            500_000_000,
+            None,
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(&mut ctx, treasury).await, 200_000_000);
+}
+
+#[tokio::test]
+async fn waived_and_free_entries_pay_nothing() {
+    let treasury = Pubkey::new_unique();
+    let mut ctx = start(treasury).await;
+    let authority = ctx.payer.pubkey();
+    let invited = funded_user(&mut ctx).await;
+
+    let result = send(
+        &mut ctx,
+        &[grant_fee_waiver_ix(invited.pubkey(), invited.pubkey())],
+        &[&invited],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(
+        &mut ctx,
+        &[grant_fee_waiver_ix(authority, invited.pubkey())],
+        &[],
+    )
+    .await
+    .unwrap();
+    send(
+        &mut ctx,
+        &[initialize_account_with_fee_ix(
+            invited.pubkey(),
+            treasury,
+            0,
+            Some(fee_waiver_pda(&invited.pubkey())),
+        )],
+        &[&invited],
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(&mut ctx, treasury).await, 0);
+
+    send(
+        &mut ctx,
+        &[revoke_fee_waiver_ix(authority, invited.pubkey())],
+        &[],
+    )
+    .await
+    .unwrap();
+    assert!(ctx
+        .banks_client
+        .get_account(fee_waiver_pda(&invited.pubkey()))
+        .await
+        .unwrap()
+        .is_none());
+
+    // Free tier for everybody
+    send(&mut ctx, &[set_entry_fee_ix(authority, 0)], &[])
+        .await
+        .unwrap();
+    let user = funded_user(&mut ctx).await;
+    send(
+        &mut ctx,
+        &[initialize_account_with_fee_ix(
+            user.pubkey(),
+            treasury,
+            0,
+            None,
+        )],
+        &[&user],
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(&mut ctx, treasury).await, 0);
+}
@@ -0,0 +1,344 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus, PositionType, UserAccount};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    system_program,
+};
+
+/// 10 SOL LONG limit at 140$ with 10x leverage: 140 USDT reserved
+const LIMIT_PRICE: u64 = 140_000_000;
+const RESERVED: u64 = 140_000_000;
+
+/// SOL at 150$, the payer has a SOL/USDT account and `executor` is whitelisted
+async fn start() -> (ProgramTestContext, Pubkey, Keypair) {
+    let mut pt = program_test();
+    let price_update = Pubkey::new_unique();
+    pt.add_account(price_update, sol_price_account(NOW));
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+
+    let treasury = Pubkey::new_unique();
+    setup_config(&mut ctx, treasury).await;
+    setup_user_account(&mut ctx, treasury).await;
+
+    let executor = setup_executor(&mut ctx).await;
+    (ctx, price_update, executor)
+}
+
+fn place_long_ix(user: Pubkey, order_id: u64, expires_at: i64) -> Instruction {
+    instruction(
+        paper_trading::accounts::PlaceLimitOrder {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            order_account: order_pda(&user, SOL_PAIR, order_id),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            user,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::PlaceLimitOrder {
+            side: PositionType::Long,
+            amount_token_out: 10_000_000_000,
+            limit_price: LIMIT_PRICE,
+            leverage: 10,
+            take_profit_price: 0,
+            stop_loss_price: 120_000_000,
+            expires_at,
+        },
+    )
+}
+
+fn cancel_ix(owner: Pubkey, order_id: u64, user: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::CancelOrder {
+            order_account: order_pda(&owner, SOL_PAIR, order_id),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            owner,
+            user,
+        },
+        paper_trading::instruction::CancelOrder {},
+    )
+}
+
+async fn order_is_closed(ctx: &mut ProgramTestContext, owner: Pubkey, order_id: u64) -> bool {
+    ctx.banks_client
+        .get_account(order_pda(&owner, SOL_PAIR, order_id))
+        .await
+        .unwrap()
+        .is_none()
+}
+
+fn fill_ix(
+    owner: Pubkey,
+    order_id: u64,
+    position_id: u64,
+    price_update: Pubkey,
+    executor: Pubkey,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::FillOrder {
+            config: config_pda(),
+            order_account: order_pda(&owner, SOL_PAIR, order_id),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            position_account: position_pda(&owner, SOL_PAIR, position_id),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            owner,
+            executor,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::FillOrder {},
+    )
+}
+
+#[tokio::test]
+async fn order_is_filled_once_the_price_crosses_the_limit() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(&mut ctx, &[place_long_ix(user, 0, 0)], &[])
+        .await
+        .unwrap();
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN - RESERVED);
+    assert_eq!(account.total_orders, 1);
+
+    // 150$ is above the limit of a LONG
+    let fill = fill_ix(user, 0, 0, price_update, executor.pubkey());
+    let result = send(&mut ctx, std::slice::from_ref(&fill), &[&executor]).await;
+    assert_program_error(result, ErrorCode::LimitPriceNotReached);
+
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_900_000_000, NOW).into(),
+    );
+    send(&mut ctx, &[fill], &[&executor]).await.unwrap();
+
+    // The order account is closed
+    assert!(order_is_closed(&mut ctx, user, 0).await);
+
+    // The position is opened at the limit price with the reserved collateral
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Active);
+    assert!(position.position_type == PositionType::Long);
+    assert_eq!(position.owner, user);
+    assert_eq!(position.entry_price, LIMIT_PRICE);
+    assert_eq!(position.collateral, RESERVED);
+    assert_eq!(position.take_profit_price, 0);
+    assert_eq!(position.stop_loss_price, 120_000_000);
+    assert_eq!(position.rent_payer, executor.pubkey());
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN - RESERVED);
+    assert_eq!(account.total_positions, 1);
+    // OrderPlaced, then PositionOpened and OrderFilled
+    assert_eq!(account.event_sequence, 3);
+
+    // An order is filled only once
+    let result = send(
+        &mut ctx,
+        &[fill_ix(user, 0, 1, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountNotInitialized.into(),
+    );
+}
+
+#[tokio::test]
+async fn fill_returns_the_rent_to_its_payers() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_900_000_000, NOW).into(),
+    );
+
+    send(&mut ctx, &[place_long_ix(user, 0, 0)], &[])
+        .await
+        .unwrap();
+    let order_rent = ctx
+        .banks_client
+        .get_balance(order_pda(&user, SOL_PAIR, 0))
+        .await
+        .unwrap();
+    let balance = ctx.banks_client.get_balance(user).await.unwrap();
+    let executor_balance = ctx
+        .banks_client
+        .get_balance(executor.pubkey())
+        .await
+        .unwrap();
+    send(
+        &mut ctx,
+        &[fill_ix(user, 0, 0, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await
+    .unwrap();
+
+    // The rent of the order goes back to the owner, who also paid the fee of the 2 signatures
+    assert_eq!(
+        ctx.banks_client.get_balance(user).await.unwrap(),
+        balance + order_rent - 10_000
+    );
+
+    let position = position_pda(&user, SOL_PAIR, 0);
+    send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[])
+        .await
+        .unwrap();
+
+    // The rent can't be sent to the owner
+    let result = send(&mut ctx, &[close_position_account_ix(user, user, 0)], &[]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::ConstraintAddress.into(),
+    );
+
+    send(
+        &mut ctx,
+        &[close_position_account_ix(user, executor.pubkey(), 0)],
+        &[],
+    )
+    .await
+    .unwrap();
+    // The executor got back the rent of the position it paid (the fees are on the payer)
+    assert_eq!(
+        ctx.banks_client
+            .get_balance(executor.pubkey())
+            .await
+            .unwrap(),
+        executor_balance
+    );
+    assert!(ctx
+        .banks_client
+        .get_account(position)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn cancel_releases_the_reserved_collateral() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(&mut ctx, &[place_long_ix(user, 0, 0)], &[])
+        .await
+        .unwrap();
+    send(&mut ctx, &[cancel_ix(user, 0, user)], &[])
+        .await
+        .unwrap();
+
+    // The order account is closed
+    assert!(order_is_closed(&mut ctx, user, 0).await);
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN);
+    // OrderPlaced and OrderCancelled
+    assert_eq!(account.event_sequence, 2);
+
+    let result = send(&mut ctx, &[cancel_ix(user, 0, user)], &[]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountNotInitialized.into(),
+    );
+
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_900_000_000, NOW).into(),
+    );
+    let result = send(
+        &mut ctx,
+        &[fill_ix(user, 0, 0, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountNotInitialized.into(),
+    );
+}
+
+#[tokio::test]
+async fn expired_order_is_cancelled_by_anyone() {
+    let (mut ctx, _, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(&mut ctx, &[place_long_ix(user, 0, NOW + 60)], &[])
+        .await
+        .unwrap();
+    let rent = ctx
+        .banks_client
+        .get_balance(order_pda(&user, SOL_PAIR, 0))
+        .await
+        .unwrap();
+
+    // Only the owner cancels a live order
+    let result = send(
+        &mut ctx,
+        &[cancel_ix(user, 0, executor.pubkey())],
+        &[&executor],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    set_clock(&mut ctx, NOW + 60).await;
+    let balance = ctx.banks_client.get_balance(user).await.unwrap();
+    send(
+        &mut ctx,
+        &[cancel_ix(user, 0, executor.pubkey())],
+        &[&executor],
+    )
+    .await
+    .unwrap();
+
+    assert!(order_is_closed(&mut ctx, user, 0).await);
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN);
+    // The rent goes back to the owner, who also paid the fee of the 2 signatures
+    assert_eq!(
+        ctx.banks_client.get_balance(user).await.unwrap(),
+        balance + rent - 10_000
+    );
+}
+
+#[tokio::test]
+async fn fill_is_restricted_to_executors_and_live_orders() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_900_000_000, NOW).into(),
+    );
+
+    send(&mut ctx, &[place_long_ix(user, 0, NOW + 60)], &[])
+        .await
+        .unwrap();
+
+    let result = send(&mut ctx, &[fill_ix(user, 0, 0, price_update, user)], &[]).await;
+    assert_program_error(result, ErrorCode::UnauthorizedExecutor);
+
+    set_clock(&mut ctx, NOW + 60).await;
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_900_000_000, NOW + 60).into(),
+    );
+    let result = send(
+        &mut ctx,
+        &[fill_ix(user, 0, 0, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::OrderExpired);
+
+    // An expiry in the past is rejected when placing
+    let result = send(&mut ctx, &[place_long_ix(user, 1, NOW)], &[]).await;
+    assert_program_error(result, ErrorCode::InvalidOrderExpiry);
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus, TradeHistory, UserAccount};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+};
+
+/// Payer opens a 10 SOL LONG at 150$ with 10x leverage:
+/// collateral 150 USDT, liquidation price 150 * (1 - 10% + 0.5%) = 135.75$
+async fn start() -> (ProgramTestContext, Pubkey, Keypair) {
+    let (mut ctx, price_update, treasury) = start_with_position(long_10_sol(10)).await;
+    let keeper = setup_other_user(&mut ctx, treasury).await;
+
+    (ctx, price_update, keeper)
+}
+
+fn liquidate_ix(owner: Pubkey, liquidator: Pubkey, price_update: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::LiquidatePosition {
+            config: config_pda(),
+            position_account: position_pda(&owner, SOL_PAIR, 0),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            trade_history: trade_history_pda(&owner, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            liquidator_account: user_account_pda(&liquidator, SOL_PAIR),
+            liquidator,
+        },
+        paper_trading::instruction::LiquidatePosition {},
+    )
+}
+
+#[tokio::test]
+async fn position_above_liquidation_price_is_not_liquidated() {
+    let (mut ctx, price_update, keeper) = start().await;
+    let owner = ctx.payer.pubkey();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_600_000_000, NOW).into(),
+    );
+
+    let result = send(
+        &mut ctx,
+        &[liquidate_ix(owner, keeper.pubkey(), price_update)],
+        &[&keeper],
+    )
+    .await;
+
+    assert_program_error(result, ErrorCode::PositionNotLiquidatable);
+}
+
+#[tokio::test]
+async fn keeper_liquidates_and_receives_the_fee() {
+    let (mut ctx, price_update, keeper) = start().await;
+    let owner = ctx.payer.pubkey();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_570_000_000, NOW).into(),
+    );
+
+    send(
+        &mut ctx,
+        &[liquidate_ix(owner, keeper.pubkey(), price_update)],
+        &[&keeper],
+    )
+    .await
+    .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&owner, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+
+    // 150 USDT of collateral - 143 USDT of loss = 7 USDT, minus 1% of the collateral
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&owner, SOL_PAIR)).await;
+    assert_eq!(
+        account.token_in_balance,
+        INITIAL_TOKEN_IN - 150_000_000 + 5_500_000
+    );
+    assert_eq!(account.realized_pnl, -144_500_000);
+    assert_eq!(account.fees_paid, 1_500_000);
+    assert_eq!(account.losing_trades, 1);
+    // PositionOpened and PositionClosed
+    assert_eq!(account.event_sequence, 2);
+
+    // The position and its history record agree with the account, net of the fee
+    assert_eq!(position.realized_pnl, -144_500_000);
+    let history: TradeHistory = fetch(&mut ctx, trade_history_pda(&owner, SOL_PAIR)).await;
+    assert_eq!(history.total_records, 1);
+    assert_eq!(history.records[0].realized_pnl, -144_500_000);
+
+    let keeper_account: UserAccount =
+        fetch(&mut ctx, user_account_pda(&keeper.pubkey(), SOL_PAIR)).await;
+    assert_eq!(
+        keeper_account.token_in_balance,
+        INITIAL_TOKEN_IN + 1_500_000
+    );
+    // The fee credit is numbered in the keeper's own sequence
+    assert_eq!(keeper_account.event_sequence, 1);
+}
+
+#[tokio::test]
+async fn owner_cannot_liquidate_its_own_position() {
+    let (mut ctx, price_update, _) = start().await;
+    let owner = ctx.payer.pubkey();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(13_570_000_000, NOW).into(),
+    );
+
+    let result = send(&mut ctx, &[liquidate_ix(owner, owner, price_update)], &[]).await;
+
+    assert_program_error(result, ErrorCode::SelfLiquidation);
+}
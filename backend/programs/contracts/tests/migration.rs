@@ -0,0 +1,283 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+use common::*;
+use paper_trading::{
+    ErrorCode, PositionAccount, PositionStatus, ProgramConfig, UserAccount, CONFIG_VERSION,
+    DEFAULT_ENTRY_FEE_LAMPORTS, DEFAULT_MAX_CONFIDENCE_BPS, DEFAULT_MAX_PRICE_AGE,
+    POSITION_ACCOUNT_VERSION, USER_ACCOUNT_VERSION,
+};
+use solana_sdk::{account::Account, signature::Signer, system_program};
+
+/// UserAccount as written before the decimals were stored:
+/// owner, pair_index, token_in_balance, token_out_balance, total_positions, created_at
+/// in an allocation of 8 + size_of::<UserAccount>() (72 bytes)
+fn legacy_user_account(owner: Pubkey) -> Account {
+    let mut data = UserAccount::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(owner.as_ref());
+    data.push(SOL_PAIR);
+    data.extend_from_slice(&9_500_000_000u64.to_le_bytes());
+    data.extend_from_slice(&3_000_000_000u64.to_le_bytes());
+    data.extend_from_slice(&4u64.to_le_bytes());
+    data.extend_from_slice(&(NOW - 86_400).to_le_bytes());
+    data.resize(8 + 72, 0);
+
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: paper_trading::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// PositionAccount as written before the margin fields: a 10 SOL LONG at 150$
+/// (TP 200$, SL 100$) in an allocation of 8 + size_of::<PositionAccount>() (96 bytes)
+fn legacy_position_account(owner: Pubkey) -> Account {
+    let mut data = PositionAccount::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(owner.as_ref());
+    data.push(SOL_PAIR);
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(0); // Long
+    data.extend_from_slice(&10_000_000_000u64.to_le_bytes());
+    data.extend_from_slice(&150_000_000u64.to_le_bytes());
+    data.extend_from_slice(&200_000_000u64.to_le_bytes());
+    data.extend_from_slice(&100_000_000u64.to_le_bytes());
+    data.push(0); // Active
+    data.extend_from_slice(&(NOW - 3_600).to_le_bytes());
+    data.extend_from_slice(&0i64.to_le_bytes());
+    data.resize(8 + 96, 0);
+
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: paper_trading::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// ProgramConfig as deployed: authority, treasury, executors, bump with one executor
+/// in the hard-coded allocation for 10 executors (397 bytes)
+fn legacy_config(authority: Pubkey, treasury: Pubkey, executor: Pubkey) -> Account {
+    let mut data = ProgramConfig::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(authority.as_ref());
+    data.extend_from_slice(treasury.as_ref());
+    data.extend_from_slice(&1u32.to_le_bytes());
+    data.extend_from_slice(executor.as_ref());
+    data.push(Pubkey::find_program_address(&[b"config"], &paper_trading::ID).1);
+    data.resize(8 + 32 + 32 + 4 + (32 * 10) + 1, 0);
+
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner: paper_trading::ID,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn migrate_position_ix(user: Pubkey, position_id: u64) -> solana_sdk::instruction::Instruction {
+    instruction(
+        paper_trading::accounts::MigratePosition {
+            position_account: position_pda(&user, SOL_PAIR, position_id),
+            user_account: user_account_pda(&user, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            user,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::MigratePosition { position_id },
+    )
+}
+
+fn migrate_config_ix(authority: Pubkey) -> solana_sdk::instruction::Instruction {
+    instruction(
+        paper_trading::accounts::MigrateConfig {
+            config: config_pda(),
+            authority,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::MigrateConfig {},
+    )
+}
+
+fn migrate_ix(user: Pubkey) -> solana_sdk::instruction::Instruction {
+    instruction(
+        paper_trading::accounts::MigrateUserAccount {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            user,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::MigrateUserAccount {},
+    )
+}
+
+#[tokio::test]
+async fn legacy_user_account_is_migrated() {
+    let mut pt = program_test();
+    let price_update = Pubkey::new_unique();
+    pt.add_account(price_update, sol_price_account(NOW));
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+    setup_config(&mut ctx, Pubkey::new_unique()).await;
+
+    let user = ctx.payer.pubkey();
+    ctx.set_account(
+        &user_account_pda(&user, SOL_PAIR),
+        &legacy_user_account(user).into(),
+    );
+
+    // The legacy layout can't trade before the migration, it is even too short
+    // to be read as the current layout
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountDidNotDeserialize.into(),
+    );
+
+    send(&mut ctx, &[migrate_ix(user)], &[]).await.unwrap();
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.owner, user);
+    assert_eq!(account.pair_index, SOL_PAIR);
+    assert_eq!(account.token_in_balance, 9_500_000_000);
+    assert_eq!(account.token_out_balance, 3_000_000_000);
+    assert_eq!(account.token_in_decimals, 6);
+    assert_eq!(account.token_out_decimals, 9);
+    assert_eq!(account.total_positions, 4);
+    assert_eq!(account.created_at, NOW - 86_400);
+    assert_eq!(account.version, USER_ACCOUNT_VERSION);
+    assert_eq!(account.total_orders, 0);
+
+    // A second migration is rejected
+    let result = send(&mut ctx, &[migrate_ix(user)], &[]).await;
+    assert_program_error(result, ErrorCode::AccountAlreadyMigrated);
+
+    // Accounts of that age have no TradeHistory yet
+    send(
+        &mut ctx,
+        &[
+            initialize_trade_history_ix(user),
+            buy_ix(user, price_update, 1_000_000_000),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn legacy_position_is_migrated() {
+    let mut pt = program_test();
+    let price_update = Pubkey::new_unique();
+    pt.add_account(price_update, sol_price_account_at(16_000_000_000, NOW));
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+    let treasury = Pubkey::new_unique();
+    setup_config(&mut ctx, treasury).await;
+    setup_user_account(&mut ctx, treasury).await;
+
+    let user = ctx.payer.pubkey();
+    ctx.set_account(
+        &position_pda(&user, SOL_PAIR, 0),
+        &legacy_position_account(user).into(),
+    );
+
+    let result = send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[]).await;
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountDidNotDeserialize.into(),
+    );
+
+    send(&mut ctx, &[migrate_position_ix(user, 0)], &[])
+        .await
+        .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.owner, user);
+    assert_eq!(position.amount_token_out, 10_000_000_000);
+    assert_eq!(position.entry_price, 150_000_000);
+    assert_eq!(position.take_profit_price, 200_000_000);
+    assert_eq!(position.stop_loss_price, 100_000_000);
+    assert!(position.status == PositionStatus::Active);
+    assert_eq!(position.opened_at, NOW - 3_600);
+    // 1x with what the first version debited: 10 SOL * 150$ without the decimals
+    assert_eq!(position.leverage, 1);
+    assert_eq!(position.collateral, 1_500_000_000_000);
+    assert_eq!(position.maintenance_margin_bps, 50);
+    assert_eq!(position.liquidation_price, 750_000);
+    assert_eq!(position.version, POSITION_ACCOUNT_VERSION);
+    assert_eq!(position.rent_payer, user);
+
+    let result = send(&mut ctx, &[migrate_position_ix(user, 0)], &[]).await;
+    assert_program_error(result, ErrorCode::AccountAlreadyMigrated);
+
+    // The collateral comes back with 100 USDT of profit
+    send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[])
+        .await
+        .unwrap();
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(
+        account.token_in_balance,
+        INITIAL_TOKEN_IN + 1_500_000_000_000 + 100_000_000
+    );
+}
+
+#[tokio::test]
+async fn legacy_config_is_migrated() {
+    let mut ctx = program_test().start_with_context().await;
+    let authority = ctx.payer.pubkey();
+    let treasury = Pubkey::new_unique();
+    let executor = Pubkey::new_unique();
+    ctx.set_account(
+        &config_pda(),
+        &legacy_config(authority, treasury, executor).into(),
+    );
+
+    // Only the authority migrates the config
+    let other = solana_sdk::signature::Keypair::new();
+    let fund_ix =
+        solana_sdk::system_instruction::transfer(&authority, &other.pubkey(), 1_000_000_000);
+    let result = send(
+        &mut ctx,
+        &[fund_ix, migrate_config_ix(other.pubkey())],
+        &[&other],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    // resize_config would leave the new fields at 0
+    let result = send(&mut ctx, &[resize_config_ix(authority)], &[]).await;
+    assert_program_error(result, ErrorCode::AccountNotMigrated);
+
+    send(&mut ctx, &[migrate_config_ix(authority)], &[])
+        .await
+        .unwrap();
+
+    let config: ProgramConfig = fetch(&mut ctx, config_pda()).await;
+    assert_eq!(config.authority, authority);
+    assert_eq!(config.treasury, treasury);
+    assert_eq!(config.authorized_executors, vec![executor]);
+    // The oracle limits didn't exist, the defaults are back-filled
+    assert_eq!(config.max_price_age, DEFAULT_MAX_PRICE_AGE);
+    assert_eq!(config.max_confidence_bps, DEFAULT_MAX_CONFIDENCE_BPS);
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert!(!config.paused);
+    assert!(!config.is_pair_halted(SOL_PAIR));
+    // Not a free entry
+    assert_eq!(config.entry_fee_lamports, DEFAULT_ENTRY_FEE_LAMPORTS);
+    let account = ctx
+        .banks_client
+        .get_account(config_pda())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), 8 + ProgramConfig::INIT_SPACE);
+
+    let result = send(&mut ctx, &[migrate_config_ix(authority)], &[]).await;
+    assert_program_error(result, ErrorCode::AccountAlreadyMigrated);
+}
@@ -0,0 +1,309 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{get_pair_price, ErrorCode, UserAccount};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{account::Account, signature::Signer};
+
+async fn start(price_accounts: Vec<(Pubkey, Account)>) -> (ProgramTestContext, Pubkey) {
+    let mut pt = program_test();
+    for (address, account) in price_accounts {
+        pt.add_account(address, account);
+    }
+
+    let mut ctx = pt.start_with_context().await;
+    set_clock(&mut ctx, NOW).await;
+
+    let treasury = Pubkey::new_unique();
+    setup_config(&mut ctx, treasury).await;
+    setup_user_account(&mut ctx, treasury).await;
+
+    let user = ctx.payer.pubkey();
+    (ctx, user)
+}
+
+#[tokio::test]
+async fn buy_uses_the_oracle_price() {
+    let price_update = Pubkey::new_unique();
+    let (mut ctx, user) = start(vec![(price_update, sol_price_account(NOW - 5))]).await;
+
+    send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[])
+        .await
+        .unwrap();
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    // 2 SOL at 150$ = 300 USDT
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN - 300_000_000);
+    assert_eq!(account.token_out_balance, 2_000_000_000);
+}
+
+#[tokio::test]
+async fn stale_price_is_rejected() {
+    let price_update = Pubkey::new_unique();
+    let (mut ctx, user) = start(vec![(price_update, sol_price_account(NOW - 61))]).await;
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[]).await;
+
+    assert_program_error(result, ErrorCode::StalePrice);
+}
+
+#[tokio::test]
+async fn feed_of_another_pair_is_rejected() {
+    let price_update = Pubkey::new_unique();
+    let btc_price = price_update_account(BTC_FEED, 6_000_000_000_000, 1_000_000, -8, NOW);
+    let (mut ctx, user) = start(vec![(price_update, btc_price)]).await;
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[]).await;
+
+    assert_program_error(result, ErrorCode::PriceFeedMismatch);
+}
+
+#[tokio::test]
+async fn wide_confidence_is_rejected() {
+    let price_update = Pubkey::new_unique();
+    // 5$ of confidence on a 150$ price (> 2%)
+    let uncertain_price = price_update_account(SOL_FEED, 15_000_000_000, 500_000_000, -8, NOW);
+    let (mut ctx, user) = start(vec![(price_update, uncertain_price)]).await;
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[]).await;
+
+    assert_program_error(result, ErrorCode::PriceConfidenceTooWide);
+}
+
+#[tokio::test]
+async fn price_account_not_owned_by_pyth_is_rejected() {
+    let price_update = Pubkey::new_unique();
+    let mut fake_price = sol_price_account(NOW);
+    fake_price.owner = Pubkey::new_unique();
+    let (mut ctx, user) = start(vec![(price_update, fake_price)]).await;
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[]).await;
+
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram.into(),
+    );
+}
+
+#[tokio::test]
+async fn price_older_than_the_last_trade_is_rejected() {
+    let recent_price = Pubkey::new_unique();
+    let older_price = Pubkey::new_unique();
+    let (mut ctx, user) = start(vec![
+        (recent_price, sol_price_account(NOW - 5)),
+        (older_price, sol_price_account_at(14_000_000_000, NOW - 30)),
+    ])
+    .await;
+
+    send(&mut ctx, &[buy_ix(user, recent_price, 2_000_000_000)], &[])
+        .await
+        .unwrap();
+
+    let result = send(&mut ctx, &[buy_ix(user, older_price, 1_000_000_000)], &[]).await;
+    assert_program_error(result, ErrorCode::OutdatedPrice);
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.last_price_time, NOW - 5);
+}
+
+#[tokio::test]
+async fn close_price_from_before_the_opening_is_rejected() {
+    let entry_price = Pubkey::new_unique();
+    let close_price = Pubkey::new_unique();
+    let (mut ctx, user) = start(vec![
+        (entry_price, sol_price_account(NOW - 10)),
+        (close_price, sol_price_account_at(16_000_000_000, NOW)),
+    ])
+    .await;
+
+    send(
+        &mut ctx,
+        &[open_position_ix(
+            user,
+            entry_price,
+            0,
+            paper_trading::instruction::OpenLongPosition {
+                amount_token_out: 10_000_000_000,
+                leverage: 1,
+                take_profit_price: 200_000_000,
+                stop_loss_price: 100_000_000,
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    // The update of the opening is recent enough, but older than the position
+    let result = send(&mut ctx, &[close_position_ix(user, entry_price, 0)], &[]).await;
+    assert_program_error(result, ErrorCode::OutdatedPrice);
+
+    send(&mut ctx, &[close_position_ix(user, close_price, 0)], &[])
+        .await
+        .unwrap();
+}
+
+fn account_info<'a>(key: &'a Pubkey, account: &'a mut Account) -> AccountInfo<'a> {
+    AccountInfo::new(
+        key,
+        false,
+        false,
+        &mut account.lamports,
+        &mut account.data,
+        &account.owner,
+        false,
+        0,
+    )
+}
+
+/// Read a price the way the ECS systems do, from the accounts of the configured program
+async fn pair_price(
+    ctx: &mut ProgramTestContext,
+    mut price_update: Account,
+    config: Option<Account>,
+    pair_index: u8,
+    not_before: i64,
+    at: i64,
+) -> Result<u64> {
+    let mut config = match config {
+        Some(config) => config,
+        None => ctx
+            .banks_client
+            .get_account(config_pda())
+            .await
+            .unwrap()
+            .unwrap(),
+    };
+    let mut pair_config = ctx
+        .banks_client
+        .get_account(pair_config_pda(SOL_PAIR))
+        .await
+        .unwrap()
+        .unwrap();
+    let keys = [
+        Pubkey::new_unique(),
+        pair_config_pda(SOL_PAIR),
+        config_pda(),
+    ];
+    get_pair_price(
+        &account_info(&keys[0], &mut price_update),
+        &account_info(&keys[1], &mut pair_config),
+        &account_info(&keys[2], &mut config),
+        pair_index,
+        not_before,
+        at,
+    )
+}
+
+#[tokio::test]
+async fn pair_price_uses_the_registered_feed() {
+    let (mut ctx, _) = start(vec![]).await;
+
+    let price = pair_price(&mut ctx, sol_price_account(NOW - 5), None, SOL_PAIR, 0, NOW).await;
+    assert_eq!(price.unwrap(), 150_000_000);
+
+    let btc_price = price_update_account(BTC_FEED, 6_000_000_000_000, 1_000_000, -8, NOW);
+    let result = pair_price(&mut ctx, btc_price, None, SOL_PAIR, 0, NOW).await;
+    assert_eq!(result.unwrap_err(), ErrorCode::PriceFeedMismatch.into());
+
+    let result = pair_price(
+        &mut ctx,
+        sol_price_account(NOW - 61),
+        None,
+        SOL_PAIR,
+        0,
+        NOW,
+    )
+    .await;
+    assert_eq!(result.unwrap_err(), ErrorCode::StalePrice.into());
+
+    // Published before the opening of the position
+    let result = pair_price(
+        &mut ctx,
+        sol_price_account(NOW - 5),
+        None,
+        SOL_PAIR,
+        NOW,
+        NOW,
+    )
+    .await;
+    assert_eq!(result.unwrap_err(), ErrorCode::OutdatedPrice.into());
+}
+
+#[tokio::test]
+async fn pair_price_at_a_past_time() {
+    let (mut ctx, _) = start(vec![]).await;
+    let end_time = NOW - 3_600;
+
+    // Settlement of a competition that ended an hour ago: fresh at end_time is enough
+    let price = pair_price(
+        &mut ctx,
+        sol_price_account(end_time - 5),
+        None,
+        SOL_PAIR,
+        0,
+        end_time,
+    )
+    .await;
+    assert_eq!(price.unwrap(), 150_000_000);
+
+    let result = pair_price(
+        &mut ctx,
+        sol_price_account(end_time - 61),
+        None,
+        SOL_PAIR,
+        0,
+        end_time,
+    )
+    .await;
+    assert_eq!(result.unwrap_err(), ErrorCode::StalePrice.into());
+
+    // A price from after the end can't value the positions
+    let result = pair_price(
+        &mut ctx,
+        sol_price_account(end_time + 1),
+        None,
+        SOL_PAIR,
+        0,
+        end_time,
+    )
+    .await;
+    assert_eq!(result.unwrap_err(), ErrorCode::PricePublishedTooLate.into());
+}
+
+#[tokio::test]
+async fn pair_price_rejects_the_config_of_another_pair() {
+    let (mut ctx, _) = start(vec![]).await;
+
+    let result = pair_price(&mut ctx, sol_price_account(NOW), None, 1, 0, NOW).await;
+
+    assert_eq!(result.unwrap_err(), ErrorCode::PairMismatch.into());
+}
+
+#[tokio::test]
+async fn pair_price_rejects_a_config_not_owned_by_the_program() {
+    let (mut ctx, _) = start(vec![]).await;
+    let mut fake_config = ctx
+        .banks_client
+        .get_account(config_pda())
+        .await
+        .unwrap()
+        .unwrap();
+    fake_config.owner = Pubkey::new_unique();
+
+    let result = pair_price(
+        &mut ctx,
+        sol_price_account(NOW),
+        Some(fake_config),
+        SOL_PAIR,
+        0,
+        NOW,
+    )
+    .await;
+
+    assert_eq!(
+        result.unwrap_err(),
+        anchor_lang::error::ErrorCode::AccountOwnedByWrongProgram.into()
+    );
+}
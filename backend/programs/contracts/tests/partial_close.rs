@@ -0,0 +1,121 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus, UserAccount};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{instruction::Instruction, signature::Signer};
+
+/// Payer opens a 10 SOL LONG at 150$ with 1x leverage (1500 USDT of collateral),
+/// SOL then moves to 160$
+async fn start() -> (ProgramTestContext, Pubkey) {
+    let (mut ctx, price_update, _) = start_with_position(long_10_sol(1)).await;
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(16_000_000_000, NOW).into(),
+    );
+
+    (ctx, price_update)
+}
+
+fn close_partial_ix(user: Pubkey, price_update: Pubkey, amount_token_out: u64) -> Instruction {
+    instruction(
+        paper_trading::accounts::ClosePositionManual {
+            position_account: position_pda(&user, SOL_PAIR, 0),
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::ClosePositionPartial { amount_token_out },
+    )
+}
+
+#[tokio::test]
+async fn partial_close_realizes_the_pnl_of_the_closed_amount() {
+    let (mut ctx, price_update) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(
+        &mut ctx,
+        &[close_partial_ix(user, price_update, 4_000_000_000)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    // 600 USDT of collateral released + 40 USDT of profit on 4 SOL
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(
+        account.token_in_balance,
+        INITIAL_TOKEN_IN - 1_500_000_000 + 640_000_000
+    );
+    // PositionOpened and PositionReduced
+    assert_eq!(account.event_sequence, 2);
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Active);
+    assert_eq!(position.amount_token_out, 6_000_000_000);
+    assert_eq!(position.collateral, 900_000_000);
+    assert_eq!(position.entry_price, 150_000_000);
+
+    // Closing the rest closes the position
+    send(
+        &mut ctx,
+        &[close_partial_ix(user, price_update, 6_000_000_000)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN + 100_000_000);
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+}
+
+#[tokio::test]
+async fn partial_close_amount_is_bounded_by_the_position() {
+    let (mut ctx, price_update) = start().await;
+    let user = ctx.payer.pubkey();
+
+    let result = send(&mut ctx, &[close_partial_ix(user, price_update, 0)], &[]).await;
+    assert_program_error(result, ErrorCode::InvalidCloseAmount);
+
+    let result = send(
+        &mut ctx,
+        &[close_partial_ix(user, price_update, 10_000_000_001)],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::InvalidCloseAmount);
+}
+
+#[tokio::test]
+async fn partial_close_keeps_the_min_order_size() {
+    let (mut ctx, price_update) = start().await;
+    let user = ctx.payer.pubkey();
+
+    // The SOL pair has a min order size of 1_000 lamports
+    let result = send(
+        &mut ctx,
+        &[close_partial_ix(user, price_update, 9_999_999_001)],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::RemainingSizeTooSmall);
+
+    send(
+        &mut ctx,
+        &[close_partial_ix(user, price_update, 9_999_999_000)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Active);
+    assert_eq!(position.amount_token_out, 1_000);
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus, PositionType};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+    system_program,
+};
+
+/// Payer opens a 10 SOL LONG at 150$ (TP 200$, SL 100$) with a whitelisted executor
+async fn start() -> (ProgramTestContext, Pubkey, Keypair) {
+    let (mut ctx, price_update, _) = start_with_position(long_10_sol(1)).await;
+    let executor = setup_executor(&mut ctx).await;
+
+    (ctx, price_update, executor)
+}
+
+fn place_order_ix(user: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::PlaceLimitOrder {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            order_account: order_pda(&user, SOL_PAIR, 0),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            user,
+            system_program: system_program::ID,
+        },
+        paper_trading::instruction::PlaceLimitOrder {
+            side: PositionType::Long,
+            amount_token_out: 1_000_000_000,
+            limit_price: 140_000_000,
+            leverage: 1,
+            take_profit_price: 0,
+            stop_loss_price: 0,
+            expires_at: 0,
+        },
+    )
+}
+
+fn set_pause_ix(pauser: Pubkey, paused: bool) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTradingStatus {
+            config: config_pda(),
+            pauser,
+        },
+        paper_trading::instruction::SetPause { paused },
+    )
+}
+
+fn halt_pair_ix(pauser: Pubkey, pair_index: u8, halted: bool) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTradingStatus {
+            config: config_pda(),
+            pauser,
+        },
+        paper_trading::instruction::HaltPair { pair_index, halted },
+    )
+}
+
+fn execute_tp_sl_ix(owner: Pubkey, price_update: Pubkey, executor: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::ExecuteTPSL {
+            config: config_pda(),
+            position_account: position_pda(&owner, SOL_PAIR, 0),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            trade_history: trade_history_pda(&owner, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            executor,
+        },
+        paper_trading::instruction::ExecuteTpSl {},
+    )
+}
+
+#[tokio::test]
+async fn pause_blocks_trades_but_not_closes() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    // The executor is not a pauser
+    let result = send(
+        &mut ctx,
+        &[set_pause_ix(executor.pubkey(), true)],
+        &[&executor],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::Unauthorized);
+
+    send(&mut ctx, &[set_pause_ix(user, true)], &[])
+        .await
+        .unwrap();
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[]).await;
+    assert_program_error(result, ErrorCode::TradingPaused);
+    let result = send(
+        &mut ctx,
+        &[open_position_ix(user, price_update, 1, long_10_sol(1))],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::TradingPaused);
+    let result = send(&mut ctx, &[place_order_ix(user)], &[]).await;
+    assert_program_error(result, ErrorCode::TradingPaused);
+
+    send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[])
+        .await
+        .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+
+    send(&mut ctx, &[set_pause_ix(user, false)], &[])
+        .await
+        .unwrap();
+    send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn halted_pair_still_executes_tp_sl() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(&mut ctx, &[halt_pair_ix(user, SOL_PAIR, true)], &[])
+        .await
+        .unwrap();
+
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[]).await;
+    assert_program_error(result, ErrorCode::PairHalted);
+    let result = send(
+        &mut ctx,
+        &[open_position_ix(user, price_update, 1, long_10_sol(1))],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::PairHalted);
+    let result = send(&mut ctx, &[place_order_ix(user)], &[]).await;
+    assert_program_error(result, ErrorCode::PairHalted);
+
+    // The stop loss is hit while the pair is halted
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(9_000_000_000, NOW).into(),
+    );
+    send(
+        &mut ctx,
+        &[execute_tp_sl_ix(user, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+
+    // Halting another pair doesn't resume this one
+    send(
+        &mut ctx,
+        &[
+            halt_pair_ix(user, SOL_PAIR + 9, true),
+            halt_pair_ix(user, SOL_PAIR + 9, false),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+    let result = send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[]).await;
+    assert_program_error(result, ErrorCode::PairHalted);
+
+    send(&mut ctx, &[halt_pair_ix(user, SOL_PAIR, false)], &[])
+        .await
+        .unwrap();
+    send(&mut ctx, &[buy_ix(user, price_update, 1_000_000_000)], &[])
+        .await
+        .unwrap();
+}
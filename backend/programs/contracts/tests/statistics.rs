@@ -0,0 +1,64 @@
+mod common;
+
+use common::*;
+use paper_trading::UserAccount;
+use solana_sdk::signature::Signer;
+
+#[tokio::test]
+async fn trades_and_closes_update_the_statistics() {
+    // 10 SOL LONG at 150$, closed at 160$ below: +100 USDT
+    let (mut ctx, price_update, _) = start_with_position(long_10_sol(5)).await;
+    let user = ctx.payer.pubkey();
+
+    // Spot buy of 2 SOL at 150$: 300 USDT of volume, no PnL
+    send(&mut ctx, &[buy_ix(user, price_update, 2_000_000_000)], &[])
+        .await
+        .unwrap();
+
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(16_000_000_000, NOW).into(),
+    );
+    send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[])
+        .await
+        .unwrap();
+
+    // 10 SOL SHORT at 160$ closed at 170$: -100 USDT
+    send(
+        &mut ctx,
+        &[open_position_ix(
+            user,
+            price_update,
+            1,
+            paper_trading::instruction::OpenShortPosition {
+                amount_token_out: 10_000_000_000,
+                leverage: 5,
+                take_profit_price: 100_000_000,
+                stop_loss_price: 200_000_000,
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(17_000_000_000, NOW).into(),
+    );
+    send(&mut ctx, &[close_position_ix(user, price_update, 1)], &[])
+        .await
+        .unwrap();
+
+    let account: UserAccount = fetch(&mut ctx, user_account_pda(&user, SOL_PAIR)).await;
+    assert_eq!(account.realized_pnl, 0);
+    assert_eq!(account.winning_trades, 1);
+    assert_eq!(account.losing_trades, 1);
+    assert_eq!(
+        account.total_volume,
+        300_000_000 + 1_500_000_000 + 1_600_000_000 + 1_600_000_000 + 1_700_000_000
+    );
+    assert_eq!(account.fees_paid, 0);
+    // One TradeExecuted, two PositionOpened and two PositionClosed events
+    assert_eq!(account.event_sequence, 5);
+    assert_eq!(account.token_in_balance, INITIAL_TOKEN_IN - 300_000_000);
+}
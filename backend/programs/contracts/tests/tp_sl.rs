@@ -0,0 +1,210 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+};
+
+/// Payer opens a 10 SOL LONG at 150$ (TP 200$, SL 100$), SOL then moves to 160$
+async fn start() -> (ProgramTestContext, Pubkey, Keypair) {
+    let (mut ctx, price_update, _) = start_with_position(long_10_sol(1)).await;
+    let executor = setup_executor(&mut ctx).await;
+
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(16_000_000_000, NOW).into(),
+    );
+
+    (ctx, price_update, executor)
+}
+
+fn update_tp_sl_ix(
+    user: Pubkey,
+    price_update: Pubkey,
+    take_profit_price: u64,
+    stop_loss_price: u64,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTpSl {
+            position_account: position_pda(&user, SOL_PAIR, 0),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::UpdateTpSl {
+            take_profit_price,
+            stop_loss_price,
+        },
+    )
+}
+
+fn execute_tp_sl_ix(owner: Pubkey, price_update: Pubkey, executor: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::ExecuteTPSL {
+            config: config_pda(),
+            position_account: position_pda(&owner, SOL_PAIR, 0),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            trade_history: trade_history_pda(&owner, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            executor,
+        },
+        paper_trading::instruction::ExecuteTpSl {},
+    )
+}
+
+#[tokio::test]
+async fn stop_is_moved_above_the_entry_price() {
+    let (mut ctx, price_update, _) = start().await;
+    let user = ctx.payer.pubkey();
+
+    // 155$ is above the entry but below the current price
+    send(
+        &mut ctx,
+        &[update_tp_sl_ix(
+            user,
+            price_update,
+            180_000_000,
+            155_000_000,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.take_profit_price, 180_000_000);
+    assert_eq!(position.stop_loss_price, 155_000_000);
+
+    let result = send(
+        &mut ctx,
+        &[update_tp_sl_ix(
+            user,
+            price_update,
+            180_000_000,
+            165_000_000,
+        )],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::InvalidStopLossPrice);
+
+    let result = send(
+        &mut ctx,
+        &[update_tp_sl_ix(user, price_update, 155_000_000, 0)],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::InvalidTakeProfitPrice);
+}
+
+#[tokio::test]
+async fn cleared_take_profit_is_not_executed() {
+    let (mut ctx, price_update, executor) = start().await;
+    let user = ctx.payer.pubkey();
+
+    send(
+        &mut ctx,
+        &[update_tp_sl_ix(user, price_update, 0, 100_000_000)],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    // 250$ would have hit the old TP
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(25_000_000_000, NOW).into(),
+    );
+    let result = send(
+        &mut ctx,
+        &[execute_tp_sl_ix(user, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::ConditionNotMet);
+
+    // The stop still closes the position
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(9_000_000_000, NOW).into(),
+    );
+    send(
+        &mut ctx,
+        &[execute_tp_sl_ix(user, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await
+    .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+}
+
+#[tokio::test]
+async fn only_the_owner_updates_tp_sl() {
+    let (mut ctx, price_update, executor) = start().await;
+    let owner = ctx.payer.pubkey();
+
+    let mut ix = update_tp_sl_ix(owner, price_update, 0, 0);
+    ix.accounts[4] = solana_sdk::instruction::AccountMeta::new_readonly(executor.pubkey(), true);
+    let result = send(&mut ctx, &[ix], &[&executor]).await;
+
+    assert_error(
+        result,
+        anchor_lang::error::ErrorCode::ConstraintSeeds.into(),
+    );
+}
+
+#[tokio::test]
+async fn market_open_accepts_no_tp_sl() {
+    let (mut ctx, price_update, _) = start().await;
+    let user = ctx.payer.pubkey();
+
+    // Same rule as the limit orders: 0 is no TP/SL, a set TP/SL is on the right side
+    let result = send(
+        &mut ctx,
+        &[open_position_ix(
+            user,
+            price_update,
+            1,
+            paper_trading::instruction::OpenShortPosition {
+                amount_token_out: 1_000_000_000,
+                leverage: 1,
+                take_profit_price: 170_000_000,
+                stop_loss_price: 0,
+            },
+        )],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::InvalidTakeProfitPrice);
+
+    send(
+        &mut ctx,
+        &[open_position_ix(
+            user,
+            price_update,
+            1,
+            paper_trading::instruction::OpenShortPosition {
+                amount_token_out: 1_000_000_000,
+                leverage: 1,
+                take_profit_price: 0,
+                stop_loss_price: 0,
+            },
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 1)).await;
+    assert!(position.status == PositionStatus::Active);
+    assert_eq!(position.take_profit_price, 0);
+    assert_eq!(position.stop_loss_price, 0);
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use bytemuck::Zeroable;
+use common::*;
+use paper_trading::{ErrorCode, TradeHistory, TradeRecord, TradeRecordKind, TRADE_HISTORY_LEN};
+use solana_sdk::{instruction::Instruction, signature::Signer};
+
+fn sell_ix(user: Pubkey, price_update: Pubkey, amount_token_out: u64) -> Instruction {
+    instruction(
+        paper_trading::accounts::Trade {
+            user_account: user_account_pda(&user, SOL_PAIR),
+            trade_history: trade_history_pda(&user, SOL_PAIR),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::Sell { amount_token_out },
+    )
+}
+
+#[tokio::test]
+async fn trades_are_recorded_and_closed_position_rent_returned() {
+    let (mut ctx, price_update, _) =
+        start_with_position(paper_trading::instruction::OpenShortPosition {
+            amount_token_out: 10_000_000_000,
+            leverage: 2,
+            take_profit_price: 100_000_000,
+            stop_loss_price: 200_000_000,
+        })
+        .await;
+    let user = ctx.payer.pubkey();
+
+    send(
+        &mut ctx,
+        &[
+            buy_ix(user, price_update, 2_000_000_000),
+            sell_ix(user, price_update, 500_000_000),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    // An active position can't be deallocated
+    let result = send(&mut ctx, &[close_position_account_ix(user, user, 0)], &[]).await;
+    assert_program_error(result, ErrorCode::PositionNotClosed);
+
+    ctx.set_account(
+        &price_update,
+        &sol_price_account_at(14_000_000_000, NOW).into(),
+    );
+    send(&mut ctx, &[close_position_ix(user, price_update, 0)], &[])
+        .await
+        .unwrap();
+
+    let position = position_pda(&user, SOL_PAIR, 0);
+    let rent = ctx
+        .banks_client
+        .get_account(position)
+        .await
+        .unwrap()
+        .unwrap()
+        .lamports;
+    let balance_before = ctx.banks_client.get_balance(user).await.unwrap();
+
+    send(&mut ctx, &[close_position_account_ix(user, user, 0)], &[])
+        .await
+        .unwrap();
+
+    assert!(ctx
+        .banks_client
+        .get_account(position)
+        .await
+        .unwrap()
+        .is_none());
+    // The rent is returned, minus the transaction fee
+    let balance_after = ctx.banks_client.get_balance(user).await.unwrap();
+    assert_eq!(balance_after, balance_before + rent - 5_000);
+
+    let history: TradeHistory = fetch(&mut ctx, trade_history_pda(&user, SOL_PAIR)).await;
+    assert_eq!(history.owner, user);
+    assert_eq!(history.pair_index, SOL_PAIR);
+    // Deallocating the position doesn't add a record, its close is already there
+    assert_eq!(history.total_records, 3);
+
+    let buy = history.records[0];
+    assert_eq!(buy.kind, TradeRecordKind::Buy as u8);
+    assert_eq!(buy.amount, 2_000_000_000);
+    assert_eq!(buy.price, 150_000_000);
+    assert_eq!(buy.timestamp, NOW);
+
+    let sell = history.records[1];
+    assert_eq!(sell.kind, TradeRecordKind::Sell as u8);
+    assert_eq!(sell.amount, 500_000_000);
+    assert_eq!(sell.price, 150_000_000);
+
+    let close = history.records[2];
+    assert_eq!(close.kind, TradeRecordKind::Short as u8);
+    assert_eq!(close.position_id, 0);
+    assert_eq!(close.amount, 10_000_000_000);
+    assert_eq!(close.entry_price, 150_000_000);
+    assert_eq!(close.price, 140_000_000);
+    assert_eq!(close.realized_pnl, 100_000_000);
+    assert_eq!(close.timestamp, NOW);
+}
+
+#[test]
+fn history_overwrites_the_oldest_records() {
+    let mut history = TradeHistory::zeroed();
+
+    for position_id in 0..TRADE_HISTORY_LEN as u64 + 3 {
+        history.push(TradeRecord {
+            position_id,
+            ..TradeRecord::zeroed()
+        });
+    }
+
+    assert_eq!(history.total_records, TRADE_HISTORY_LEN as u64 + 3);
+    assert_eq!(history.records[0].position_id, TRADE_HISTORY_LEN as u64);
+    assert_eq!(history.records[2].position_id, TRADE_HISTORY_LEN as u64 + 2);
+    assert_eq!(history.records[3].position_id, 3);
+}
@@ -0,0 +1,194 @@
+mod common;
+
+use anchor_lang::prelude::*;
+use common::*;
+use paper_trading::{ErrorCode, PositionAccount, PositionStatus, TrailingStopMode};
+use solana_program_test::ProgramTestContext;
+use solana_sdk::{
+    instruction::Instruction,
+    signature::{Keypair, Signer},
+};
+
+/// Payer opens a 10 SOL position at 150$ with a whitelisted executor
+async fn start(data: impl anchor_lang::InstructionData) -> (ProgramTestContext, Pubkey, Keypair) {
+    let (mut ctx, price_update, _) = start_with_position(data).await;
+    let executor = setup_executor(&mut ctx).await;
+
+    (ctx, price_update, executor)
+}
+
+fn set_price(ctx: &mut ProgramTestContext, price_update: Pubkey, price: i64) {
+    ctx.set_account(&price_update, &sol_price_account_at(price, NOW).into());
+}
+
+fn set_trailing_stop_ix(
+    user: Pubkey,
+    price_update: Pubkey,
+    mode: TrailingStopMode,
+    distance: u64,
+) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTpSl {
+            position_account: position_pda(&user, SOL_PAIR, 0),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            user,
+        },
+        paper_trading::instruction::SetTrailingStop { mode, distance },
+    )
+}
+
+fn update_trailing_stop_ix(owner: Pubkey, price_update: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::UpdateTrailingStop {
+            position_account: position_pda(&owner, SOL_PAIR, 0),
+            config: config_pda(),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+        },
+        paper_trading::instruction::UpdateTrailingStop {},
+    )
+}
+
+fn execute_tp_sl_ix(owner: Pubkey, price_update: Pubkey, executor: Pubkey) -> Instruction {
+    instruction(
+        paper_trading::accounts::ExecuteTPSL {
+            config: config_pda(),
+            position_account: position_pda(&owner, SOL_PAIR, 0),
+            user_account: user_account_pda(&owner, SOL_PAIR),
+            trade_history: trade_history_pda(&owner, SOL_PAIR),
+            pair_config: pair_config_pda(SOL_PAIR),
+            price_update,
+            executor,
+        },
+        paper_trading::instruction::ExecuteTpSl {},
+    )
+}
+
+#[tokio::test]
+async fn long_trailing_stop_ratchets_up_and_closes() {
+    let (mut ctx, price_update, executor) = start(paper_trading::instruction::OpenLongPosition {
+        amount_token_out: 10_000_000_000,
+        leverage: 1,
+        take_profit_price: 200_000_000,
+        stop_loss_price: 100_000_000,
+    })
+    .await;
+    let user = ctx.payer.pubkey();
+
+    // 10% below 160$
+    set_price(&mut ctx, price_update, 16_000_000_000);
+    send(
+        &mut ctx,
+        &[set_trailing_stop_ix(
+            user,
+            price_update,
+            TrailingStopMode::Bps,
+            1_000,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.high_water_mark, 160_000_000);
+    assert_eq!(position.stop_loss_price, 144_000_000);
+
+    // The crank needs no signature once the price is higher
+    set_price(&mut ctx, price_update, 17_000_000_000);
+    send(
+        &mut ctx,
+        &[update_trailing_stop_ix(user, price_update)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.high_water_mark, 170_000_000);
+    assert_eq!(position.stop_loss_price, 153_000_000);
+
+    // The stop never moves back
+    set_price(&mut ctx, price_update, 16_500_000_000);
+    let result = send(
+        &mut ctx,
+        &[update_trailing_stop_ix(user, price_update)],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::ConditionNotMet);
+
+    set_price(&mut ctx, price_update, 15_200_000_000);
+    send(
+        &mut ctx,
+        &[execute_tp_sl_ix(user, price_update, executor.pubkey())],
+        &[&executor],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert!(position.status == PositionStatus::Closed);
+}
+
+#[tokio::test]
+async fn short_trailing_stop_follows_the_lowest_price() {
+    let (mut ctx, price_update, _) = start(paper_trading::instruction::OpenShortPosition {
+        amount_token_out: 10_000_000_000,
+        leverage: 1,
+        take_profit_price: 100_000_000,
+        stop_loss_price: 200_000_000,
+    })
+    .await;
+    let user = ctx.payer.pubkey();
+
+    // An absolute distance must stay below the price
+    let result = send(
+        &mut ctx,
+        &[set_trailing_stop_ix(
+            user,
+            price_update,
+            TrailingStopMode::Absolute,
+            150_000_000,
+        )],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::InvalidTrailingStop);
+
+    // 5$ above 150$
+    send(
+        &mut ctx,
+        &[set_trailing_stop_ix(
+            user,
+            price_update,
+            TrailingStopMode::Absolute,
+            5_000_000,
+        )],
+        &[],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.stop_loss_price, 155_000_000);
+
+    set_price(&mut ctx, price_update, 14_000_000_000);
+    send(
+        &mut ctx,
+        &[update_trailing_stop_ix(user, price_update)],
+        &[],
+    )
+    .await
+    .unwrap();
+    let position: PositionAccount = fetch(&mut ctx, position_pda(&user, SOL_PAIR, 0)).await;
+    assert_eq!(position.high_water_mark, 140_000_000);
+    assert_eq!(position.stop_loss_price, 145_000_000);
+
+    set_price(&mut ctx, price_update, 14_100_000_000);
+    let result = send(
+        &mut ctx,
+        &[update_trailing_stop_ix(user, price_update)],
+        &[],
+    )
+    .await;
+    assert_program_error(result, ErrorCode::ConditionNotMet);
+}
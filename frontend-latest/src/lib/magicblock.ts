@@ -5,7 +5,11 @@ import type { Adapter, SignerWalletAdapter } from '@solana/wallet-adapter-base';
 export const MAGICBLOCK_RPC = 'https://rpc.magicblock.app/devnet/';
 export const SOLANA_RPC = 'https://api.devnet.solana.com';
 
-// Paper Trading Program ID from the contract
+// Paper Trading Program ID of the legacy deployment
+// NOTE: this client still encodes the legacy instructions (caller supplied prices, no leverage)
+// and decodes the legacy UserAccount/PositionAccount layouts. The source of that program is kept in
+// frontend-latest/main.rs until the client is ported to backend/programs/contracts
+// (b6NjCktqaB4KqTvsNmYTJ9KBfwMJ7Sh4hMJ2Xz26YR3: Pyth price updates, pair configs, versioned accounts).
 export const PAPER_TRADING_PROGRAM_ID = new PublicKey('ENpbjfPxXx9fLhLDcqbLsHmo25LRU4fW9RXFfrqbKbmo');
 
 export const COMPONENT_IDS = {
@@ -56,6 +60,9 @@ export enum PositionDirection {
 
 export const WORLD_ID = new BN(2409);
 export const WORLD_INSTANCE_ID = new PublicKey('CVndFdiiuFhkcLEQy71JomGwgZT8Lqeq9oFuU14E9Ngk');
+// Entity spawned with create_competition (see backend/world-config.json). The one spawned
+// before the Competition component got its configuration fields can't be decoded anymore.
+export const COMPETITION_ENTITY: PublicKey | null = null;
 
 export class MagicBlockClient {
 	connection: Connection;
@@ -296,7 +303,7 @@ export class MagicBlockClient {
 				seed: Buffer.from(currentWallet.publicKey.toBytes()),
 			});
 
-			this.competitionEntity = new PublicKey('5ebXENtrEamPapRhzMGjvrcavWwrEwWiY4Yftjx3wUsk');
+			this.competitionEntity = COMPETITION_ENTITY;
 		} catch (e) {
 		}
 	}
@@ -1128,7 +1135,7 @@ export class MagicBlockClient {
 					const data = accountInfo.account.data;
 					const dataView = new DataView(data.buffer, data.byteOffset, data.byteLength);
 					
-					// Parse according to PositionAccount struct from main.rs:
+					// Parse according to the legacy PositionAccount struct:
 					// owner: Pubkey (32), pair_index: u8 (1), position_id: u64 (8)
 					// position_type: PositionType (1), amount_token_out: u64 (8)
 					// entry_price: u64 (8), take_profit_price: u64 (8), stop_loss_price: u64 (8)